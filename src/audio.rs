@@ -1,3 +1,4 @@
+use reqwest::{header, StatusCode};
 use rodio::{
     cpal::{
         self,
@@ -5,39 +6,116 @@ use rodio::{
         DeviceNameError,
     },
     decoder::DecoderError,
-    DevicesError, OutputStream, OutputStreamHandle, PlayError, Sink, StreamError,
+    Decoder, DevicesError, PlayError, Source, StreamError,
 };
+use serde::{Deserialize, Serialize};
 use std::{
-    cmp::Ordering,
+    collections::VecDeque,
     fmt::Display,
-    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    fs::File,
+    io::{self, BufReader, Seek, SeekFrom},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Mutex, Weak,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 use tokio::time;
 
 use crate::{
-    config::Station,
-    decoder::{Mp3Error, Mp3StreamDecoder},
+    config::{ListenConfig, PipeConfig, SilenceAction, Station, ZappingConfig},
+    decoder::{self, Mp3Error, Mp3StreamDecoder},
+    dsp::{
+        channels::{ChannelMapper, ChannelSettings},
+        gain::{GainControl, GainRamp},
+        limiter::Limiter,
+        meter::{LevelMeter, MeterTap},
+        split, DspChain, DspSource,
+    },
+    listen::ListenBus,
+    output::{self, Mixer, OutputStream, StreamSettings, VirtualHost},
+    settings::{DeviceProfile, MirrorOutput},
+    silence::{SilenceDetector, SilenceIncident, SilencePolicy},
+    zapping::{PreconnectedReader, Preconnection},
 };
 
 pub enum PlayerMsg {
-    Stop,
+    /// Fades out over the given duration and terminates the player
+    Stop(Duration),
     SetVolume(u8),
+    /// Fades out (or back in) without changing the volume
+    SetMuted(bool),
+}
+
+/// Events which are emitted by the playback thread.
+pub enum PlayerEvent {
+    /// Playback has ended and there was nothing left in the queue.
+    Stopped,
+    /// Playback has advanced to the next item of the queue or to the silence fallback station.
+    Advanced(Station),
+    /// The stream has been silent for too long.
+    Silence(SilenceIncident),
 }
 
 const STREAM_CONNECT_TIMEOUT_SECS: u8 = 10;
-const VOLUME_TRANSITION_STEP_DELAY_MS: u64 = 12;
+const STREAM_CONNECT_POLL_INTERVAL_MS: u64 = 50;
+/// How often the playback thread checks whether the sink has run empty.
+const PLAYER_POLL_INTERVAL_MS: u64 = 500;
+/// How much longer than the fade itself a terminating player waits for silence.
+const FADE_OUT_GRACE_MS: u64 = 500;
+/// How much longer than the fade itself opening new outputs waits for the previous ones to close.
+const OUTPUT_CLOSE_GRACE_MS: u64 = 1000;
+
+/// URLs with this prefix refer to local files instead of network streams.
+pub const FILE_URL_PREFIX: &str = "file://";
+
+type BoxedSource = Box<dyn Source<Item = i16> + Send>;
+type Queue = Arc<Mutex<VecDeque<Station>>>;
 
 pub struct Player {
     player_tx: Sender<PlayerMsg>,
     player_rx: Option<Receiver<PlayerMsg>>,
-    stopped_tx: Option<Sender<()>>,
-    stopped_rx: Receiver<()>,
+    event_tx: Option<Sender<PlayerEvent>>,
+    event_rx: Receiver<PlayerEvent>,
     curr_station: Option<Station>,
+    queue: Queue,
+    /// The playback position of the current source in milliseconds
+    position_ms: Arc<AtomicU64>,
+    /// The duration of the crossfade when switching between stations
+    crossfade: Duration,
+    /// The duration of the fade when starting, stopping or changing the volume
+    fade: Duration,
+    /// Streams which are connected in the background so that switching to them is instant
+    preconnections: Vec<Preconnection>,
+    dsp: DspChain,
+    channels: ChannelSettings,
+    meter: Arc<LevelMeter>,
+    /// Encodes the played audio for the listeners of the listen stream
+    listen: Option<Arc<ListenBus>>,
+    silence: Option<SilencePolicy>,
+    /// The most recent period of silence which was detected
+    last_silence: Option<SilenceIncident>,
     volume_percent: u8,
-    alsa_device_idx: usize,
+    muted: bool,
+    max_volume_percent: u8,
+    device: OutputDevice,
+    stream: StreamSettings,
+    pipe: Option<PipeConfig>,
+    mirrors: Vec<MirrorOutput>,
+    /// The gains of the mirror outputs, they outlive restarts of the player
+    mirror_gains: Vec<Arc<GainControl>>,
+    /// The stream settings of the mirror outputs, taken from the profiles of their devices
+    mirror_streams: Vec<StreamSettings>,
+    /// The outputs which are played on by the playback threads
+    outputs: Weak<OpenOutputs>,
+    /// Whether the outputs are reopened on the next play even if they have not changed
+    reopen_outputs: bool,
+    /// Virtual outputs are kept open between plays, so that e.g. a WAV file is not overwritten
+    kept_outputs: Option<Arc<OpenOutputs>>,
 }
 
 #[derive(Error, Debug)]
@@ -50,11 +128,19 @@ pub enum Error {
 
     Mp3(Mp3Error),
     Reqwest(reqwest::Error),
+    Io(io::Error),
     NoSuchDevice,
     NoDefaultAudioDevice,
+    NoSuchHost(String),
+    HostUnavailable(String),
+    NoPipeOutput,
+    NoPipeReader(String),
 
     NotPlaying,
     StreamConnectTimeout(u8),
+    NoSuchQueueItem(usize),
+    NoSuchMirror(usize),
+    Opus(opus::Error),
 }
 
 impl Display for Error {
@@ -66,13 +152,34 @@ impl Display for Error {
             Error::RodioDevices(err) => write!(f, "{err}"),
             Error::CPALDeviceName(err) => write!(f, "{err}"),
             Error::Reqwest(err) => write!(f, "{err}"),
+            Error::Io(err) => write!(f, "{err}"),
             Error::NoSuchDevice => write!(f, "this device does not exist"),
             Error::NoDefaultAudioDevice => write!(f, "no default audio device could be identified"),
+            Error::NoSuchHost(name) => write!(
+                f,
+                "the audio host `{name}` does not exist, available hosts: {}",
+                cpal::available_hosts()
+                    .iter()
+                    .map(|id| id.name())
+                    .chain(VirtualHost::ALL.iter().map(|host| host.name()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Error::HostUnavailable(name) => {
+                write!(f, "the audio host `{name}` is currently not available")
+            }
+            Error::NoPipeOutput => write!(f, "the pipe output is not configured"),
+            Error::NoPipeReader(path) => {
+                write!(f, "no process is reading from the pipe at `{path}`")
+            }
             Error::Mp3(err) => write!(f, "mp3 decode error: {err}"),
+            Error::Opus(err) => write!(f, "opus encode error: {err}"),
             Error::NotPlaying => write!(f, "the player is currently not playing anything"),
             Error::StreamConnectTimeout(secs) => {
                 write!(f, "stream did not connect after {secs} second timeout")
             }
+            Error::NoSuchQueueItem(idx) => write!(f, "the queue has no item at position {idx}"),
+            Error::NoSuchMirror(idx) => write!(f, "there is no mirror output at position {idx}"),
         }
     }
 }
@@ -107,182 +214,770 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
 impl From<Mp3Error> for Error {
     fn from(err: Mp3Error) -> Self {
         Self::Mp3(err)
     }
 }
 
+impl From<opus::Error> for Error {
+    fn from(err: opus::Error) -> Self {
+        Self::Opus(err)
+    }
+}
+
 impl From<DeviceNameError> for Error {
     fn from(err: DeviceNameError) -> Self {
         Self::CPALDeviceName(err)
     }
 }
 
-pub(crate) fn list_host_devices() -> Result<Vec<String>, Error> {
-    let host = cpal::default_host();
-    let devices = host.output_devices()?;
-    devices
-        .into_iter()
-        .map(|dev| dev.name().map_err(Error::CPALDeviceName))
+/// Identifies an output device by its name.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct OutputDevice {
+    /// The audio host (e.g. `ALSA` or `JACK`) which provides the device, the default host if empty
+    #[serde(default)]
+    pub host: Option<String>,
+    pub name: String,
+    /// The position of the device in the device list.
+    /// Since positions change whenever devices are added or removed,
+    /// the index is only used if no device with the name exists.
+    pub index: usize,
+}
+
+/// An output device of the host as it is reported by `/api/devices`.
+#[derive(Serialize)]
+pub struct DeviceInfo {
+    pub index: usize,
+    pub name: String,
+    #[serde(rename = "isDefault")]
+    pub is_default: bool,
+    /// The stream configurations which are supported by the device
+    pub configs: Vec<DeviceConfig>,
+}
+
+/// An audio host which radio was built with.
+#[derive(Serialize)]
+pub struct HostInfo {
+    pub name: &'static str,
+    /// Whether the host can currently be used
+    pub available: bool,
+    #[serde(rename = "isDefault")]
+    pub is_default: bool,
+}
+
+#[derive(Serialize)]
+pub struct DeviceConfig {
+    pub channels: u16,
+    #[serde(rename = "minSampleRate")]
+    pub min_sample_rate: u32,
+    #[serde(rename = "maxSampleRate")]
+    pub max_sample_rate: u32,
+    #[serde(rename = "sampleFormat")]
+    pub sample_format: String,
+}
+
+pub(crate) fn list_hosts() -> Vec<HostInfo> {
+    let available = cpal::available_hosts();
+    let default = cpal::default_host().id();
+    cpal::ALL_HOSTS
+        .iter()
+        .map(|id| HostInfo {
+            name: id.name(),
+            available: available.contains(id),
+            is_default: *id == default,
+        })
+        .chain(VirtualHost::ALL.iter().map(|host| HostInfo {
+            name: host.name(),
+            available: true,
+            is_default: false,
+        }))
         .collect()
 }
 
-pub(crate) fn default_device_idx() -> Result<usize, Error> {
-    let host = cpal::default_host();
+/// Returns the audio host with the given name (case insensitive) or the default host.
+fn host_by_name(name: Option<&str>) -> Result<cpal::Host, Error> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+    let Some(id) = cpal::ALL_HOSTS
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+    else {
+        return Err(Error::NoSuchHost(name.to_string()));
+    };
+    cpal::host_from_id(*id).map_err(|_| Error::HostUnavailable(id.name().to_string()))
+}
+
+/// Returns the name of the given host or the default host.
+pub(crate) fn host_name(name: Option<&str>) -> Result<&'static str, Error> {
+    if let Some(host) = name.and_then(VirtualHost::from_name) {
+        return Ok(host.name());
+    }
+    Ok(host_by_name(name)?.id().name())
+}
+
+pub(crate) fn list_host_devices(host: Option<&str>) -> Result<Vec<DeviceInfo>, Error> {
+    if let Some(host) = host.and_then(VirtualHost::from_name) {
+        return Ok(host
+            .device_names()
+            .into_iter()
+            .enumerate()
+            .map(|(index, name)| DeviceInfo {
+                index,
+                name,
+                is_default: index == 0,
+                configs: vec![],
+            })
+            .collect());
+    }
+
+    let host = host_by_name(host)?;
+    let default_name = host.default_output_device().and_then(|d| d.name().ok());
+
+    let mut devices = vec![];
+    for (index, device) in host.output_devices()?.enumerate() {
+        // the index stays the position in the device list, which `find_device` relies on
+        let name = match device.name() {
+            Ok(name) => name,
+            Err(err) => {
+                debug!("Skipping output device {index} without a name: {err}");
+                continue;
+            }
+        };
+        // devices which are in use by another application may not report their configs
+        let configs = match device.supported_output_configs() {
+            Ok(configs) => configs
+                .map(|config| DeviceConfig {
+                    channels: config.channels(),
+                    min_sample_rate: config.min_sample_rate().0,
+                    max_sample_rate: config.max_sample_rate().0,
+                    sample_format: config.sample_format().to_string(),
+                })
+                .collect(),
+            Err(err) => {
+                debug!("Could not query configs of device `{name}`: {err}");
+                vec![]
+            }
+        };
+        devices.push(DeviceInfo {
+            index,
+            is_default: default_name.as_ref() == Some(&name),
+            name,
+            configs,
+        });
+    }
+    Ok(devices)
+}
+
+/// Lists the names of the output devices, this is cheaper than listing the full device info.
+pub(crate) fn list_device_names(host: Option<&str>) -> Result<Vec<String>, Error> {
+    if let Some(host) = host.and_then(VirtualHost::from_name) {
+        return Ok(host.device_names());
+    }
+
+    let host = host_by_name(host)?;
     let devices = host.output_devices()?;
+    Ok(devices
+        .into_iter()
+        .filter_map(|dev| match dev.name() {
+            Ok(name) => Some(name),
+            Err(err) => {
+                debug!("Skipping output device without a name: {err}");
+                None
+            }
+        })
+        .collect())
+}
+
+pub(crate) fn default_device(host_name: Option<&str>) -> Result<OutputDevice, Error> {
+    match host_name.and_then(VirtualHost::from_name) {
+        Some(VirtualHost::Null) => return Ok(output::null_device()),
+        Some(VirtualHost::Pipe) => return Ok(output::pipe_device()),
+        Some(VirtualHost::Wav) => return Err(Error::NoDefaultAudioDevice),
+        None => {}
+    }
+
+    let host = host_by_name(host_name)?;
     let Some(default_device) = host.default_output_device() else {
-            return Err(Error::NoDefaultAudioDevice);
+        return Err(Error::NoDefaultAudioDevice);
     };
-    for (idx, device) in devices.into_iter().enumerate() {
-        if device.name()? == default_device.name()? {
-            return Ok(idx);
+    let name = default_device.name()?;
+    for (index, device) in host.output_devices()?.enumerate() {
+        if device.name().is_ok_and(|n| n == name) {
+            return Ok(OutputDevice {
+                host: host_name.map(|h| h.to_string()),
+                name,
+                index,
+            });
         }
     }
-    unreachable!("the audio devices include the default audio device")
+    // hosts without sound cards may report a default device which cannot be enumerated
+    Err(Error::NoDefaultAudioDevice)
 }
 
-fn output_stream_by_device_idx(idx: usize) -> Result<(OutputStream, OutputStreamHandle), Error> {
-    let host = cpal::default_host();
-    let devices = host.output_devices().unwrap();
-    match devices.into_iter().nth(idx) {
-        Some(device) => Ok(OutputStream::try_from_device(&device)?),
-        None => Err(Error::NoSuchDevice),
+/// Finds the output device by its name, falling back to its index.
+pub(crate) fn find_device(output_device: &OutputDevice) -> Result<cpal::Device, Error> {
+    let host = host_by_name(output_device.host.as_deref())?;
+    let mut devices: Vec<cpal::Device> = host.output_devices()?.collect();
+
+    if let Some(idx) = devices
+        .iter()
+        .position(|d| d.name().is_ok_and(|name| name == output_device.name))
+    {
+        return Ok(devices.swap_remove(idx));
+    }
+    if output_device.index < devices.len() {
+        warn!(
+            "Output device `{}` does not exist, using device {} instead",
+            output_device.name, output_device.index
+        );
+        return Ok(devices.swap_remove(output_device.index));
     }
+    Err(Error::NoSuchDevice)
+}
+
+/// The initial state of a [`Player`].
+pub struct PlayerOptions {
+    pub volume_percent: u8,
+    pub muted: bool,
+    pub device: OutputDevice,
+    /// The duration of the crossfade when switching between stations
+    pub crossfade: Duration,
+    /// The duration of the fade when starting, stopping or changing the volume
+    pub fade: Duration,
+    /// The highest volume which can be set
+    pub max_volume_percent: u8,
+    pub dsp: DspChain,
+    pub channels: ChannelSettings,
+    pub silence: Option<SilencePolicy>,
+    pub stream: StreamSettings,
+    /// The format of the pipe output
+    pub pipe: Option<PipeConfig>,
+    pub mirrors: Vec<MirrorOutput>,
+    /// The stream settings of the mirror outputs
+    pub mirror_streams: Vec<StreamSettings>,
+    /// The format of the listen stream, nothing is encoded if it is not set
+    pub listen: Option<ListenConfig>,
 }
 
 impl Player {
-    pub fn new(volume_percent: u8, alsa_device_idx: usize) -> Result<Self, Error> {
+    pub fn new(options: PlayerOptions) -> Result<Self, Error> {
         let (terminate_tx, terminate_rx) = mpsc::channel();
-        let (stopped_tx, stopped_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
 
         Ok(Self {
             player_tx: terminate_tx,
             player_rx: Some(terminate_rx),
-            stopped_tx: Some(stopped_tx),
-            stopped_rx,
+            event_tx: Some(event_tx),
+            event_rx,
             curr_station: None,
-            volume_percent,
-            alsa_device_idx,
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+            position_ms: Arc::new(AtomicU64::new(0)),
+            crossfade: options.crossfade,
+            fade: options.fade,
+            preconnections: vec![],
+            dsp: options.dsp,
+            channels: options.channels,
+            meter: Arc::new(LevelMeter::new()),
+            listen: match &options.listen {
+                Some(config) => Some(Arc::new(ListenBus::new(config)?)),
+                None => None,
+            },
+            silence: options.silence,
+            last_silence: None,
+            volume_percent: options.volume_percent,
+            muted: options.muted,
+            max_volume_percent: options.max_volume_percent,
+            device: options.device,
+            stream: options.stream,
+            pipe: options.pipe,
+            mirror_gains: mirror_gains(&options.mirrors),
+            mirrors: options.mirrors,
+            mirror_streams: options.mirror_streams,
+            outputs: Weak::new(),
+            reopen_outputs: false,
+            kept_outputs: None,
         })
     }
 
+    pub fn dsp(&self) -> &DspChain {
+        &self.dsp
+    }
+
+    pub fn meter(&self) -> &LevelMeter {
+        &self.meter
+    }
+
+    pub fn listen(&self) -> Option<&Arc<ListenBus>> {
+        self.listen.as_ref()
+    }
+
     pub fn curr_station_id(&mut self) -> Option<String> {
-        match self.stopped_rx.try_recv() {
-            Ok(_) => {
-                self.stop(false).unwrap();
-                None
+        self.curr_station().map(|s| s.id.clone())
+    }
+
+    pub fn curr_station(&mut self) -> Option<&Station> {
+        loop {
+            match self.event_rx.try_recv() {
+                Ok(PlayerEvent::Advanced(station)) => {
+                    info!("Playback advanced: playing `{}`", station.name);
+                    self.curr_station = Some(station);
+                }
+                Ok(PlayerEvent::Silence(incident)) => self.last_silence = Some(incident),
+                Ok(PlayerEvent::Stopped) => {
+                    self.stop(false).unwrap();
+                    return None;
+                }
+                Err(TryRecvError::Empty) => return self.curr_station.as_ref(),
+                Err(TryRecvError::Disconnected) => {
+                    unreachable!("the sender is never disconnected")
+                }
             }
-            Err(TryRecvError::Empty) => self.curr_station.as_ref().map(|s| s.id.clone()),
-            Err(TryRecvError::Disconnected) => unreachable!("the sender is never disconnected"),
         }
     }
 
-    pub fn set_volume(&mut self, volume_percent: u8) {
-        if self.player_rx.is_none() {
-            self.player_tx
-                .send(PlayerMsg::SetVolume(volume_percent))
-                .expect("when the player is running, there must be a receiver")
+    /// Returns the most recent period of silence which was detected.
+    pub fn last_silence(&mut self) -> Option<&SilenceIncident> {
+        // process pending events first
+        self.curr_station();
+        self.last_silence.as_ref()
+    }
+
+    /// Returns the playback position of the current source.
+    pub fn position(&mut self) -> Option<Duration> {
+        self.curr_station()?;
+        Some(Duration::from_millis(
+            self.position_ms.load(AtomicOrdering::Relaxed),
+        ))
+    }
+
+    pub fn queue(&self) -> Vec<Station> {
+        self.queue.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Appends the station to the end of the queue.
+    pub fn enqueue(&mut self, station: Station) {
+        self.queue.lock().unwrap().push_back(station)
+    }
+
+    /// Inserts the station at the front of the queue so that it is played next.
+    pub fn enqueue_next(&mut self, station: Station) {
+        self.queue.lock().unwrap().push_front(station)
+    }
+
+    pub fn remove_from_queue(&mut self, idx: usize) -> Result<Station, Error> {
+        self.queue
+            .lock()
+            .unwrap()
+            .remove(idx)
+            .ok_or(Error::NoSuchQueueItem(idx))
+    }
+
+    /// Moves the queue item at position `from` to position `to`.
+    pub fn move_in_queue(&mut self, from: usize, to: usize) -> Result<(), Error> {
+        let mut queue = self.queue.lock().unwrap();
+        if to >= queue.len() {
+            return Err(Error::NoSuchQueueItem(to));
+        }
+        let station = queue.remove(from).ok_or(Error::NoSuchQueueItem(from))?;
+        queue.insert(to, station);
+        Ok(())
+    }
+
+    pub fn clear_queue(&mut self) {
+        self.queue.lock().unwrap().clear()
+    }
+
+    /// Skips to the next item of the queue.
+    /// If the queue is empty, the player is stopped instead.
+    pub async fn skip(&mut self) -> Result<(), Error> {
+        let next = self.queue.lock().unwrap().pop_front();
+        match next {
+            Some(station) => self.play(station).await,
+            None => self.stop(true),
         }
+    }
+
+    pub fn set_volume(&mut self, volume_percent: u8) {
+        self.send_to_thread(PlayerMsg::SetVolume(volume_percent));
         self.volume_percent = volume_percent
     }
 
-    pub async fn set_output_device(&mut self, idx: usize) -> Result<(), Error> {
-        debug!("Changing output device to `{idx}`, Restarting player...");
+    pub fn set_muted(&mut self, muted: bool) {
+        self.send_to_thread(PlayerMsg::SetMuted(muted));
+        self.muted = muted
+    }
 
-        self.alsa_device_idx = idx;
-        if let Some(station) = self.curr_station.clone() {
-            self.stop(true)?;
-            self.play(station.clone()).await?
+    /// Sends the message to the running player thread, if there is one.
+    /// A thread which has already quit is treated as stopped.
+    fn send_to_thread(&mut self, msg: PlayerMsg) {
+        if self.player_rx.is_none() && self.player_tx.send(msg).is_err() {
+            warn!("Player has quit unexpectedly, stopping");
+            // cannot fail, the player is running
+            let _ = self.stop(false);
+        }
+    }
+
+    /// Keeps the streams of the given stations connected in the background.
+    /// Existing connections to other stations are closed.
+    pub fn preconnect(&mut self, stations: Vec<Station>, config: &ZappingConfig) {
+        self.preconnections
+            .retain(|p| p.is_connected() && stations.iter().any(|s| s.url == p.station().url));
+
+        for station in stations {
+            if self
+                .preconnections
+                .iter()
+                .any(|p| p.station().url == station.url)
+            {
+                continue;
+            }
+            self.preconnections.push(Preconnection::open(
+                station,
+                config.buffer_kb * 1024,
+                config.bandwidth_limit_kbps,
+            ));
+        }
+    }
+
+    /// Changes the output device and applies the volume and DSP `profile` of the device.
+    pub async fn set_output_device(
+        &mut self,
+        device: OutputDevice,
+        profile: &DeviceProfile,
+    ) -> Result<(), Error> {
+        self.volume_percent = profile.volume_percent.min(self.max_volume_percent);
+        self.dsp.equalizer().set(profile.equalizer.clone());
+        self.dsp.compressor().set(
+            profile
+                .compressor
+                .active_preset(chrono::Local::now().time()),
+        );
+        self.channels = profile.channels.clone();
+        self.stream = profile.stream.clone();
+        self.change_device(device).await
+    }
+
+    pub fn device(&self) -> &OutputDevice {
+        &self.device
+    }
+
+    /// Moves playback to another output device while keeping all other settings.
+    pub async fn change_device(&mut self, device: OutputDevice) -> Result<(), Error> {
+        debug!(
+            "Changing output device to `{}`, Restarting player...",
+            device.name
+        );
+
+        self.device = device;
+        self.restart().await?;
+
+        debug!("Player restarted successfully");
+
+        Ok(())
+    }
+
+    /// Changes the channel processing, restarting playback since the channel layout may change.
+    pub async fn set_channels(&mut self, channels: ChannelSettings) -> Result<(), Error> {
+        debug!("Changing channel settings, Restarting player...");
+
+        self.channels = channels;
+        self.restart().await?;
+
+        debug!("Player restarted successfully");
+
+        Ok(())
+    }
+
+    /// Replaces the mirror outputs and their stream settings and restarts the player in order to
+    /// open or close them.
+    pub async fn set_mirrors(
+        &mut self,
+        mirrors: Vec<MirrorOutput>,
+        streams: Vec<StreamSettings>,
+    ) -> Result<(), Error> {
+        debug!("Changing mirror outputs, Restarting player...");
+
+        self.mirror_gains = mirror_gains(&mirrors);
+        self.mirrors = mirrors;
+        self.mirror_streams = streams;
+        self.restart().await?;
+
+        debug!("Player restarted successfully");
+
+        Ok(())
+    }
+
+    /// Fades the mirror output at `idx` to the volume without restarting the player.
+    pub fn set_mirror_volume(&mut self, idx: usize, volume: u8) -> Result<(), Error> {
+        let (Some(mirror), Some(gain)) = (self.mirrors.get_mut(idx), self.mirror_gains.get(idx))
+        else {
+            return Err(Error::NoSuchMirror(idx));
         };
+        mirror.volume = volume;
+        gain.fade_to(volume as f32 / 100.0, self.fade);
+        Ok(())
+    }
+
+    /// Reopens the output stream using the new settings.
+    pub async fn set_stream_settings(&mut self, stream: StreamSettings) -> Result<(), Error> {
+        debug!("Changing output stream settings, Restarting player...");
+
+        self.stream = stream;
+        self.restart().await?;
 
         debug!("Player restarted successfully");
 
         Ok(())
     }
 
+    /// Reopens the outputs of a running player, e.g. in order to open a mirror output which was
+    /// missing when the outputs were opened.
+    pub async fn reopen_outputs(&mut self) -> Result<(), Error> {
+        if self.curr_station.is_none() {
+            return Ok(());
+        }
+        debug!("Reopening outputs, Restarting player...");
+
+        self.reopen_outputs = true;
+        self.restart().await?;
+
+        debug!("Player restarted successfully");
+
+        Ok(())
+    }
+
+    async fn restart(&mut self) -> Result<(), Error> {
+        if let Some(station) = self.curr_station.clone() {
+            self.stop(true)?;
+            self.play(station).await?
+        };
+        Ok(())
+    }
+
     pub async fn play(&mut self, station: Station) -> Result<(), Error> {
         debug!("Attempting to play station `{}`", station.name);
 
-        let player_rx = match self.player_rx.take() {
-            Some(rx) => rx,
-            None => {
-                self.stop(true)?;
-                self.player_rx
-                    .take()
-                    .expect("the player was just stopped, there is a receiver now")
-            }
+        let outputs = self.open_outputs().await?;
+
+        // a running player keeps playing until the new stream is connected,
+        // then both streams are crossfaded
+        let previous_player_tx = match self.player_rx.is_none() {
+            true => Some(self.replace_channels()),
+            false => None,
         };
+        let player_rx = self
+            .player_rx
+            .take()
+            .expect("the channels were just replaced, there is a receiver now");
 
         let (outcome_tx, outcome_rx) = mpsc::channel();
-        let player_stopped_tx = self.stopped_tx.take().unwrap();
+        let player_event_tx = self.event_tx.take().unwrap();
 
-        let thread_url = station.url.to_string();
-        let player_volume = self.volume_percent;
-        let device_idx = self.alsa_device_idx;
+        let mut preconnected = self
+            .preconnections
+            .iter()
+            .position(|p| p.station().url == station.url)
+            .map(|idx| self.preconnections.remove(idx))
+            .filter(|p| {
+                let connected = p.is_connected();
+                if !connected {
+                    debug!(
+                        "Preconnected stream `{}` has ended, connecting again",
+                        station.url
+                    );
+                }
+                connected
+            })
+            .map(Preconnection::into_reader);
+
+        let mut thread_station = station.clone();
+        let mut player_volume = self.volume_percent;
+        let mut muted = self.muted;
+        let queue = self.queue.clone();
+        let fade_in = match previous_player_tx.is_some() {
+            true => self.crossfade,
+            false => self.fade,
+        };
+        let fade = self.fade;
+
+        // the previous player keeps updating its own position until it has faded out
+        self.position_ms = Arc::new(AtomicU64::new(0));
+
+        let pipeline = Pipeline {
+            dsp: self.dsp.clone(),
+            // the gain outlives restarts of the stream so that they do not cause a fade
+            gain: Arc::new(GainControl::new(0.0)),
+            channels: self.channels.clone(),
+            meter: self.meter.clone(),
+            listen: self.listen.clone(),
+            silence: self.silence.clone(),
+            silence_detected: Arc::new(AtomicBool::new(false)),
+            position_ms: self.position_ms.clone(),
+        };
+        let gain = pipeline.gain.clone();
 
         thread::spawn(move || loop {
-            match Self::create_sink(thread_url.clone(), player_volume, device_idx) {
-                Ok((sink, _output_handle)) => {
+            match Self::start_playback(
+                &mut thread_station,
+                preconnected.take(),
+                &pipeline,
+                &outputs,
+            ) {
+                Ok(mut playback) => {
                     match outcome_tx.send(Ok(())) {
                         Ok(_) => trace!("Sent stream outcome to receiver"),
                         Err(_) => trace!("Stream outcome receiver disconnected"),
                     }
+                    gain.fade_to(output_gain(player_volume, muted), fade_in);
                     loop {
-                        if sink.empty() {
-                            match station.auto_restart {
-                                // if the station supports auto restart, do not quit here
-                                true => {
-                                    debug!(
-                                        "Sink is empty, playback has ended: restarting stream..."
-                                    );
+                        if pipeline
+                            .silence_detected
+                            .swap(false, AtomicOrdering::Relaxed)
+                        {
+                            let policy = pipeline.silence.as_ref().expect("silence is detected");
+                            let incident = policy.report(&thread_station);
+                            if player_event_tx
+                                .send(PlayerEvent::Silence(incident))
+                                .is_err()
+                            {
+                                trace!("Player event receiver disconnected");
+                            }
+
+                            match (policy.config.action, &policy.fallback) {
+                                (SilenceAction::Fallback, Some(fallback))
+                                    if fallback.id != thread_station.id =>
+                                {
+                                    debug!("Stream is silent: switching to fallback station...");
+                                    thread_station = fallback.clone();
+                                    if player_event_tx
+                                        .send(PlayerEvent::Advanced(fallback.clone()))
+                                        .is_err()
+                                    {
+                                        trace!("Player event receiver disconnected");
+                                    }
+                                    break;
+                                }
+                                // the fallback station itself is reconnected
+                                (SilenceAction::Reconnect | SilenceAction::Fallback, _) => {
+                                    debug!("Stream is silent: reconnecting...");
                                     break;
                                 }
+                                (SilenceAction::None, _) => {}
+                            }
+                        }
+                        if outputs.failed() {
+                            error!("Output has failed: stopping playback...");
+                            if player_event_tx.send(PlayerEvent::Stopped).is_err() {
+                                trace!("Player event receiver disconnected");
+                            }
+                            return;
+                        }
+                        if playback.ended() {
+                            // if the station supports auto restart, do not quit here,
+                            // unless the queue is waiting for it to end
+                            if thread_station.auto_restart && queue.lock().unwrap().is_empty() {
+                                debug!("Source has ended: restarting stream...");
+                                break;
+                            }
+
+                            match Self::open_next_in_queue(&queue) {
+                                // continue with the next item of the queue on the same outputs
+                                Some((next, source)) => {
+                                    debug!("Source has ended: advancing queue...");
+                                    playback = pipeline.play(source, &next, &outputs.mixers);
+                                    if player_event_tx
+                                        .send(PlayerEvent::Advanced(next.clone()))
+                                        .is_err()
+                                    {
+                                        trace!("Player event receiver disconnected");
+                                    }
+                                    thread_station = next;
+                                    continue;
+                                }
                                 // otherwise, send a signal and terminate this thread
-                                false => {
-                                    debug!("Sink is empty, playback has ended: sending signal...");
-                                    player_stopped_tx
-                                        .send(())
-                                        .expect("the receiver is always valid");
+                                None => {
+                                    debug!("Source has ended: sending signal...");
+                                    if player_event_tx.send(PlayerEvent::Stopped).is_err() {
+                                        trace!("Player event receiver disconnected");
+                                    }
                                     return;
                                 }
                             }
                         }
-                        match player_rx.try_recv() {
-                            Ok(PlayerMsg::Stop) | Err(TryRecvError::Disconnected) => {
-                                Self::set_sink_volume_with_transition(&sink, 0.0);
+                        match player_rx.recv_timeout(Duration::from_millis(PLAYER_POLL_INTERVAL_MS))
+                        {
+                            Ok(PlayerMsg::Stop(fade_out)) => {
+                                debug!("Player is terminating...");
+                                gain.fade_to(0.0, fade_out);
+                                gain.wait_for_silence(
+                                    fade_out + Duration::from_millis(FADE_OUT_GRACE_MS),
+                                );
+                                return;
+                            }
+                            Err(RecvTimeoutError::Disconnected) => {
                                 debug!("Player is terminating...");
                                 return;
                             }
                             Ok(PlayerMsg::SetVolume(volume)) => {
-                                let target_volume = volume as f32 / 100.0;
-                                Self::set_sink_volume_with_transition(&sink, target_volume);
+                                gain.fade_to(output_gain(volume, muted), fade);
+                                player_volume = volume;
                                 debug!("Set running sink volume to {volume}%");
                             }
-                            Err(TryRecvError::Empty) => thread::sleep(Duration::from_millis(500)),
+                            Ok(PlayerMsg::SetMuted(mute)) => {
+                                gain.fade_to(output_gain(player_volume, mute), fade);
+                                muted = mute;
+                                debug!("Set running sink muted to {mute}");
+                            }
+                            Err(RecvTimeoutError::Timeout) => {}
                         };
                     }
                 }
                 Err(err) => {
-                    let _ = outcome_tx.send(Err(err));
+                    if let Err(mpsc::SendError(Err(err))) = outcome_tx.send(Err(err)) {
+                        warn!("Could not reconnect to `{}`: {err}", thread_station.url);
+                    }
+                    // the stream is reconnected after a while, unless the player has given up
+                    match player_rx.recv_timeout(Duration::from_millis(PLAYER_POLL_INTERVAL_MS)) {
+                        Ok(PlayerMsg::Stop(_)) | Err(RecvTimeoutError::Disconnected) => {
+                            debug!("Player is terminating...");
+                            return;
+                        }
+                        Ok(PlayerMsg::SetVolume(volume)) => player_volume = volume,
+                        Ok(PlayerMsg::SetMuted(mute)) => muted = mute,
+                        Err(RecvTimeoutError::Timeout) => {}
+                    }
                 }
             };
         });
 
-        for i in 0..=STREAM_CONNECT_TIMEOUT_SECS * 2 {
-            time::sleep(Duration::from_millis(500)).await;
+        let polls = STREAM_CONNECT_TIMEOUT_SECS as u64 * 1000 / STREAM_CONNECT_POLL_INTERVAL_MS;
+        for i in 0..=polls {
+            time::sleep(Duration::from_millis(STREAM_CONNECT_POLL_INTERVAL_MS)).await;
 
             match outcome_rx.try_recv() {
                 Ok(Ok(())) => {
                     info!("Stream connected: playing `{}`", station.url);
+                    self.stop_previous(previous_player_tx);
                     self.curr_station = Some(station.clone());
                     break;
                 }
                 Ok(Err(err)) => {
+                    self.stop_previous(previous_player_tx);
                     self.stop(false)?;
                     return Err(err);
                 }
-                Err(TryRecvError::Empty) if i == STREAM_CONNECT_TIMEOUT_SECS => {
+                Err(TryRecvError::Empty) if i == polls => {
+                    self.stop_previous(previous_player_tx);
+                    // the thread keeps connecting, it plays nothing once it has been stopped
+                    self.stop(true)?;
                     return Err(Error::StreamConnectTimeout(STREAM_CONNECT_TIMEOUT_SECS));
                 }
                 Err(TryRecvError::Empty) => {
@@ -297,47 +992,177 @@ impl Player {
         Ok(())
     }
 
-    fn set_sink_volume_with_transition(sink: &Sink, target_volume: f32) {
-        let mut current_volume = sink.volume();
+    /// Sends the termination signal to the player which was running before the current one.
+    /// The previous player fades out while the current one fades in.
+    fn stop_previous(&self, previous_player_tx: Option<Sender<PlayerMsg>>) {
+        if let Some(tx) = previous_player_tx {
+            match tx.send(PlayerMsg::Stop(self.crossfade)) {
+                Ok(_) => trace!("Sent termination signal to previous player"),
+                Err(_) => trace!("Previous player quit before termination signal could be sent"),
+            }
+        }
+    }
 
-        match current_volume.partial_cmp(&target_volume) {
-            Some(Ordering::Less) => {
-                while current_volume < target_volume {
-                    current_volume += 0.01;
-                    sink.set_volume(current_volume);
-                    thread::sleep(Duration::from_millis(VOLUME_TRANSITION_STEP_DELAY_MS));
-                }
-                sink.set_volume(target_volume);
+    /// Opens the station and plays it on the `outputs` through the `pipeline`.
+    fn start_playback(
+        station: &mut Station,
+        preconnected: Option<PreconnectedReader>,
+        pipeline: &Pipeline,
+        outputs: &OpenOutputs,
+    ) -> Result<Playback, Error> {
+        let source = Self::open_source(station, preconnected)?;
+        Ok(pipeline.play(source, station, &outputs.mixers))
+    }
+
+    /// Returns the outputs the playback threads play on, they are only opened if they are not
+    /// open yet. Outputs which have changed or failed are reopened once the previous ones are
+    /// closed, since audio devices may only allow a single stream.
+    async fn open_outputs(&mut self) -> Result<Arc<OpenOutputs>, Error> {
+        let outputs = Outputs {
+            device: self.device.clone(),
+            stream: self.stream.clone(),
+            pipe: self.pipe.clone(),
+            mirrors: self
+                .mirrors
+                .iter()
+                .zip(&self.mirror_gains)
+                .zip(&self.mirror_streams)
+                .filter(|((mirror, _), _)| mirror.enabled && mirror.device != self.device)
+                .map(|((mirror, gain), stream)| Mirror {
+                    device: mirror.device.clone(),
+                    stream: stream.clone(),
+                    gain: gain.clone(),
+                })
+                .collect(),
+        };
+
+        let reopen = std::mem::take(&mut self.reopen_outputs);
+        if let Some(open) = self.outputs.upgrade() {
+            if open.outputs.matches(&outputs) && !open.failed() && !reopen {
+                return Ok(open);
             }
-            Some(Ordering::Greater) => {
-                while current_volume > target_volume {
-                    current_volume -= 0.01;
-                    sink.set_volume(current_volume);
-                    thread::sleep(Duration::from_millis(VOLUME_TRANSITION_STEP_DELAY_MS));
+            let closed = open.open.clone();
+            drop(open);
+            self.kept_outputs = None;
+
+            // the previous playback threads close the outputs once they have faded out
+            let deadline =
+                Instant::now() + self.fade + Duration::from_millis(OUTPUT_CLOSE_GRACE_MS);
+            while closed.strong_count() > 0 && Instant::now() < deadline {
+                time::sleep(Duration::from_millis(STREAM_CONNECT_POLL_INTERVAL_MS)).await;
+            }
+            if closed.strong_count() > 0 {
+                warn!("Previous outputs are still open, opening the new outputs anyway");
+            }
+        }
+
+        let is_virtual = outputs.is_virtual();
+        let opened = outputs.open();
+        let open = loop {
+            match opened.try_recv() {
+                Ok(open) => break Arc::new(open?),
+                Err(TryRecvError::Empty) => {
+                    time::sleep(Duration::from_millis(STREAM_CONNECT_POLL_INTERVAL_MS)).await
+                }
+                Err(TryRecvError::Disconnected) => {
+                    unreachable!("the output thread always sends the outcome")
                 }
-                sink.set_volume(target_volume);
             }
-            Some(Ordering::Equal) | None => {}
+        };
+        self.outputs = Arc::downgrade(&open);
+        if is_virtual {
+            self.kept_outputs = Some(open.clone());
         }
+        Ok(open)
     }
 
-    fn create_sink(
-        url: String,
-        default_volume: u8,
-        device_idx: usize,
-    ) -> Result<(Sink, OutputStream), Error> {
-        let stream = reqwest::blocking::get(url)?;
+    /// Removes the next item from the queue and opens its source.
+    /// Items which cannot be opened are skipped.
+    fn open_next_in_queue(queue: &Queue) -> Option<(Station, BoxedSource)> {
+        loop {
+            let mut next = queue.lock().unwrap().pop_front()?;
+            match Self::open_source(&mut next, None) {
+                Ok(source) => return Some((next, source)),
+                Err(err) => warn!("Skipping queue item `{}`: {err}", next.name),
+            }
+        }
+    }
 
-        let source = Mp3StreamDecoder::new(stream)?;
-        let (_stream, stream_handle) = output_stream_by_device_idx(device_idx)?;
+    /// Opens and decodes the source behind the URL of the station.
+    /// Local files (prefixed with `file://`) may use any format supported by rodio,
+    /// network streams are always decoded as MP3.
+    /// If the stream is `preconnected`, the existing connection is used.
+    /// The start position of the station is reset if the source cannot start there.
+    fn open_source(
+        station: &mut Station,
+        preconnected: Option<PreconnectedReader>,
+    ) -> Result<BoxedSource, Error> {
+        let start = station.start_position;
+        if let Some(path) = station.url.strip_prefix(FILE_URL_PREFIX) {
+            return Self::open_file(Path::new(path), start);
+        }
+        if let Some(reader) = preconnected {
+            debug!("Using preconnected stream for `{}`", station.url);
+            match Mp3StreamDecoder::new(reader) {
+                Ok(source) => return Ok(Box::new(source)),
+                // the stream may have ended after it was handed over
+                Err(err) => debug!(
+                    "Preconnected stream `{}` failed: {err}, connecting again",
+                    station.url
+                ),
+            }
+        }
+        match start.is_zero() {
+            true => {
+                let stream = reqwest::blocking::get(&station.url)?;
+                Ok(Box::new(Mp3StreamDecoder::new(stream)?))
+            }
+            false => {
+                let (source, resumed) = Self::open_stream_at(&station.url, start)?;
+                if !resumed {
+                    warn!(
+                        "Stream `{}` cannot be resumed, playing from the start",
+                        station.url
+                    );
+                    station.start_position = Duration::ZERO;
+                }
+                Ok(source)
+            }
+        }
+    }
 
-        let sink = rodio::Sink::try_new(&stream_handle)?;
-        sink.set_volume(0.0);
-        sink.append(source);
+    /// Opens the local file at the `start` position.
+    /// MP3 files are seeked, other formats are decoded up to the start.
+    fn open_file(path: &Path, start: Duration) -> Result<BoxedSource, Error> {
+        let mut file = File::open(path)?;
+        let is_mp3 = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"));
+        if is_mp3 && !start.is_zero() {
+            let offset = decoder::byte_offset(BufReader::new(&file), start)?;
+            file.seek(SeekFrom::Start(offset))?;
+            return Ok(Box::new(Mp3StreamDecoder::new(BufReader::new(file))?));
+        }
 
-        Self::set_sink_volume_with_transition(&sink, default_volume as f32 / 100.0);
+        let source = Decoder::new(BufReader::new(file))?;
+        match start.is_zero() {
+            true => Ok(Box::new(source)),
+            false => Ok(Box::new(source.skip_duration(start))),
+        }
+    }
 
-        Ok((sink, _stream))
+    /// Requests the network stream from the byte offset of the `start` position on, so that
+    /// nothing before it is downloaded. Returns whether the server has sent the stream from
+    /// the offset, otherwise the stream is played from the start.
+    fn open_stream_at(url: &str, start: Duration) -> Result<(BoxedSource, bool), Error> {
+        let offset = decoder::byte_offset(reqwest::blocking::get(url)?, start)?;
+        let response = reqwest::blocking::Client::new()
+            .get(url)
+            .header(header::RANGE, format!("bytes={offset}-"))
+            .send()?
+            .error_for_status()?;
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        Ok((Box::new(Mp3StreamDecoder::new(response)?), resumed))
     }
 
     pub fn stop(&mut self, send_signal: bool) -> Result<(), Error> {
@@ -346,27 +1171,587 @@ impl Player {
             true => {
                 if send_signal {
                     // terminate the player
-                    match self.player_tx.send(PlayerMsg::Stop) {
+                    match self.player_tx.send(PlayerMsg::Stop(self.fade)) {
                         Ok(_) => trace!("Sent termination signal to player"),
                         Err(_) => trace!("Player quit before termination signal could be sent"),
                     }
                 }
 
-                // place a new set of channels into the player
-                let (tx, rx) = mpsc::channel();
-                self.player_tx = tx;
-                self.player_rx = Some(rx);
-
-                let (stopped_tx, stopped_rx) = mpsc::channel();
-                self.stopped_tx = Some(stopped_tx);
-                self.stopped_rx = stopped_rx;
+                self.replace_channels();
 
                 // set the current status to `not playing`
                 self.curr_station = None;
 
+                // preconnected streams are only useful while something is playing
+                if send_signal {
+                    self.preconnections.clear();
+                }
+
                 Ok(())
             }
             false => Err(Error::NotPlaying),
         }
     }
+
+    /// Places a new set of channels into the player, detaching it from the running thread.
+    /// Returns the sender which is connected to the running thread.
+    fn replace_channels(&mut self) -> Sender<PlayerMsg> {
+        let (tx, rx) = mpsc::channel();
+        let previous_tx = std::mem::replace(&mut self.player_tx, tx);
+        self.player_rx = Some(rx);
+
+        let (event_tx, event_rx) = mpsc::channel();
+        self.event_tx = Some(event_tx);
+        self.event_rx = event_rx;
+
+        previous_tx
+    }
+}
+
+/// Returns the gain of the output for the volume setting.
+fn output_gain(volume_percent: u8, muted: bool) -> f32 {
+    match muted {
+        true => 0.0,
+        false => volume_percent as f32 / 100.0,
+    }
+}
+
+/// The processing which is applied to every source played by a player thread.
+struct Pipeline {
+    dsp: DspChain,
+    gain: Arc<GainControl>,
+    channels: ChannelSettings,
+    meter: Arc<LevelMeter>,
+    listen: Option<Arc<ListenBus>>,
+    silence: Option<SilencePolicy>,
+    /// Raised by the silence detector of the current source
+    silence_detected: Arc<AtomicBool>,
+    position_ms: Arc<AtomicU64>,
+}
+
+/// The output devices a player plays on.
+struct Outputs {
+    device: OutputDevice,
+    stream: StreamSettings,
+    pipe: Option<PipeConfig>,
+    /// The enabled mirror outputs
+    mirrors: Vec<Mirror>,
+}
+
+/// A mirror output which is opened alongside the main output device.
+struct Mirror {
+    device: OutputDevice,
+    stream: StreamSettings,
+    gain: Arc<GainControl>,
+}
+
+/// The streams of the output devices of a player. They are shared by its playback threads,
+/// so that crossfading sources are mixed into the same streams instead of opening the devices
+/// twice. The streams are closed once the last playback thread has dropped them.
+struct OpenOutputs {
+    outputs: Outputs,
+    /// The mixer of the main output device comes first
+    mixers: Vec<OutputMixer>,
+    /// Raised once the main output can no longer play
+    failed: Arc<AtomicBool>,
+    /// Lives as long as the streams are open
+    open: Weak<()>,
+    /// Closes the streams when dropped
+    _close: Sender<()>,
+}
+
+/// The mixer of a stream which plays on an output device.
+struct OutputMixer {
+    mixer: Mixer,
+    /// The number of channels of the output device
+    channels: u16,
+    /// The gain of a mirror output, the main output is only controlled by the volume
+    gain: Option<Arc<GainControl>>,
+}
+
+impl Outputs {
+    /// Opens the main output device and the mirror outputs on a thread which owns their
+    /// streams, since the streams of audio devices cannot be moved to another thread.
+    /// Mirror outputs which cannot be opened are skipped.
+    fn open(self) -> Receiver<Result<OpenOutputs, Error>> {
+        let (opened_tx, opened_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let open = Arc::new(());
+            let (stream, mixer, channels) =
+                match OutputStream::open(&self.device, &self.stream, self.pipe.as_ref()) {
+                    Ok(opened) => opened,
+                    Err(err) => {
+                        let _ = opened_tx.send(Err(err));
+                        return;
+                    }
+                };
+            let failed = stream.failed();
+            let mut streams = vec![stream];
+            let mut mixers = vec![OutputMixer {
+                mixer,
+                channels,
+                gain: None,
+            }];
+
+            for mirror in &self.mirrors {
+                match OutputStream::open(&mirror.device, &mirror.stream, self.pipe.as_ref()) {
+                    Ok((stream, mixer, channels)) => {
+                        streams.push(stream);
+                        mixers.push(OutputMixer {
+                            mixer,
+                            channels,
+                            gain: Some(mirror.gain.clone()),
+                        });
+                    }
+                    Err(err) => warn!(
+                        "Could not open mirror output `{}`: {err}",
+                        mirror.device.name
+                    ),
+                }
+            }
+
+            let (close_tx, close_rx) = mpsc::channel::<()>();
+            let outputs = OpenOutputs {
+                outputs: self,
+                mixers,
+                failed,
+                open: Arc::downgrade(&open),
+                _close: close_tx,
+            };
+            if opened_tx.send(Ok(outputs)).is_ok() {
+                // returns once the outputs have been dropped
+                let _ = close_rx.recv();
+            }
+            debug!("Closing output streams");
+            drop(streams);
+            drop(open);
+        });
+        opened_rx
+    }
+
+    /// Returns whether the outputs play on the same streams as the `other` outputs.
+    fn matches(&self, other: &Outputs) -> bool {
+        self.device == other.device
+            && self.stream == other.stream
+            && self.pipe == other.pipe
+            && self.mirrors.len() == other.mirrors.len()
+            && self
+                .mirrors
+                .iter()
+                .zip(&other.mirrors)
+                .all(|(mirror, other)| {
+                    mirror.device == other.device
+                        && mirror.stream == other.stream
+                        && Arc::ptr_eq(&mirror.gain, &other.gain)
+                })
+    }
+
+    /// Returns whether no output plays on an audio device.
+    fn is_virtual(&self) -> bool {
+        VirtualHost::is_virtual(&self.device)
+            && self
+                .mirrors
+                .iter()
+                .all(|mirror| VirtualHost::is_virtual(&mirror.device))
+    }
+}
+
+impl OpenOutputs {
+    fn failed(&self) -> bool {
+        self.failed.load(AtomicOrdering::Relaxed)
+    }
+}
+
+/// The sources of a station which are played on the outputs.
+/// They are removed from the outputs once the playback is dropped.
+#[derive(Default)]
+struct Playback {
+    stop: Arc<AtomicBool>,
+    ended: Arc<AtomicBool>,
+}
+
+impl Playback {
+    /// Returns whether the source has been played until its end.
+    fn ended(&self) -> bool {
+        self.ended.load(AtomicOrdering::Relaxed)
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.stop.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+/// Plays the inner source until it ends or until its [`Playback`] is dropped.
+struct PlaybackSource {
+    inner: DspSource,
+    stop: Arc<AtomicBool>,
+    /// Raised once the inner source has ended, only set for the source of the main output
+    ended: Option<Arc<AtomicBool>>,
+}
+
+impl Iterator for PlaybackSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.stop.load(AtomicOrdering::Relaxed) {
+            return None;
+        }
+        let sample = self.inner.next();
+        if let (None, Some(ended)) = (sample, &self.ended) {
+            ended.store(true, AtomicOrdering::Relaxed);
+        }
+        sample
+    }
+}
+
+impl Source for PlaybackSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+fn mirror_gains(mirrors: &[MirrorOutput]) -> Vec<Arc<GainControl>> {
+    mirrors
+        .iter()
+        .map(|mirror| Arc::new(GainControl::new(mirror.volume as f32 / 100.0)))
+        .collect()
+}
+
+impl Pipeline {
+    /// Plays the source on all `outputs`, alongside the sources which are already playing.
+    fn play(&self, source: BoxedSource, station: &Station, outputs: &[OutputMixer]) -> Playback {
+        let playback = Playback::default();
+        let sources = self.build(source, station, outputs);
+        for (idx, (source, output)) in sources.into_iter().zip(outputs).enumerate() {
+            // the volume, the DSP and the channel mapping may all raise the level,
+            // so the limiter comes last in order to prevent clipping
+            let channels = idx == 0 && self.channels.boosts();
+            let boosts = self.boosts(station, output.gain.clone(), channels);
+            output.mixer.add(PlaybackSource {
+                inner: Box::new(Limiter::engaged_when(source, boosts)),
+                stop: playback.stop.clone(),
+                // the mirrors play the same source, so they run dry at the same time
+                ended: (idx == 0).then(|| playback.ended.clone()),
+            });
+        }
+        playback
+    }
+
+    /// Returns one source for each of the `outputs`.
+    fn build(
+        &self,
+        source: BoxedSource,
+        station: &Station,
+        outputs: &[OutputMixer],
+    ) -> Vec<DspSource> {
+        // local files may contain intended silence, so only network streams are watched
+        let source: BoxedSource = match &self.silence {
+            Some(policy) if !station.url.starts_with(FILE_URL_PREFIX) => Box::new(
+                SilenceDetector::new(source, &policy.config, self.silence_detected.clone()),
+            ),
+            _ => source,
+        };
+        let source = GainRamp::new(
+            self.dsp.apply(
+                PositionTracker::new(source, &self.position_ms, station.start_position),
+                station,
+            ),
+            self.gain.clone(),
+        );
+        // the levels are measured as they are heard, before they are mapped to the device channels
+        let source = MeterTap::new(source, self.meter.clone());
+        let main_channels = outputs[0].channels;
+        if outputs.len() == 1 && self.listen.is_none() {
+            return vec![self.map_channels(Box::new(source), main_channels)];
+        }
+
+        let branches = split::split(Box::new(source), outputs.len());
+        // the listeners hear what the output devices play, without affecting their timing
+        if let Some(listen) = &self.listen {
+            listen.set_title(&station.name);
+            listen.add(Limiter::engaged_when(
+                branches[0].follower(),
+                self.boosts(station, None, false),
+            ));
+        }
+        let mut branches = branches.into_iter();
+        let main = self.map_channels(Box::new(branches.next().unwrap()), main_channels);
+        // the channel settings belong to the main output device, so the mirrors play unchanged
+        let mirrors = branches
+            .zip(&outputs[1..])
+            .map(|(branch, output)| -> DspSource {
+                match &output.gain {
+                    Some(gain) => Box::new(GainRamp::new(branch, gain.clone())),
+                    None => Box::new(branch),
+                }
+            });
+        std::iter::once(main).chain(mirrors).collect()
+    }
+
+    /// Returns whether the level of the `station` can currently exceed the level it was decoded
+    /// at, taking the gain of a mirror output and the channel mapping into account.
+    /// The limiter is only engaged then, so that unity playback is not altered.
+    fn boosts(
+        &self,
+        station: &Station,
+        mirror_gain: Option<Arc<GainControl>>,
+        channels: bool,
+    ) -> impl Fn() -> bool + Send + 'static {
+        let dsp = self.dsp.clone();
+        let gain = self.gain.clone();
+        let gain_db = station.gain_db;
+        move || {
+            channels
+                || gain.boosts()
+                || mirror_gain.as_ref().is_some_and(|gain| gain.boosts())
+                || dsp.boosts(gain_db)
+        }
+    }
+
+    fn map_channels(&self, source: DspSource, device_channels: u16) -> DspSource {
+        match self.channels.is_passthrough() {
+            true => source,
+            false => Box::new(ChannelMapper::new(
+                source,
+                self.channels.clone(),
+                device_channels,
+            )),
+        }
+    }
+}
+
+/// Keeps track of the playback position of the wrapped source.
+struct PositionTracker<S>
+where
+    S: Source<Item = i16>,
+{
+    inner: S,
+    position_ms: Arc<AtomicU64>,
+    samples: u32,
+}
+
+impl<S> PositionTracker<S>
+where
+    S: Source<Item = i16>,
+{
+    fn new(inner: S, position_ms: &Arc<AtomicU64>, start: Duration) -> Self {
+        position_ms.store(start.as_millis() as u64, AtomicOrdering::Relaxed);
+        Self {
+            inner,
+            position_ms: position_ms.clone(),
+            samples: 0,
+        }
+    }
+}
+
+impl<S> Iterator for PositionTracker<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.inner.next()?;
+
+        // the position is advanced in steps of 10 milliseconds
+        self.samples += 1;
+        if self.samples >= self.inner.sample_rate() * self.inner.channels() as u32 / 100 {
+            self.samples = 0;
+            self.position_ms.fetch_add(10, AtomicOrdering::Relaxed);
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for PositionTracker<S>
+where
+    S: Source<Item = i16>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// The size of a frame at 128 kbit/s and 44.1 kHz.
+    const FRAME_LEN: usize = 417;
+    const SAMPLES_PER_FRAME: usize = 1152;
+    const ID3_TAG_LEN: usize = 110;
+
+    /// Returns an MP3 file of silent frames which starts with an ID3 tag.
+    fn mp3_file(frames: usize) -> Vec<u8> {
+        let mut file = b"ID3\x04\x00\x00\x00\x00\x00\x64".to_vec();
+        file.resize(ID3_TAG_LEN, 0);
+        for _ in 0..frames {
+            let mut frame = vec![0; FRAME_LEN];
+            // MPEG-1 Layer III, 128 kbit/s, 44.1 kHz, mono
+            frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0xc4]);
+            file.extend(frame);
+        }
+        file
+    }
+
+    /// Serves the `body` over HTTP, answering range requests if `ranges` is set.
+    /// Returns the URL and a receiver of the requested ranges.
+    fn serve(body: Vec<u8>, ranges: bool) -> (String, Receiver<Option<u64>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/episode.mp3", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        range = value.trim_end_matches('-').parse().ok();
+                    }
+                }
+                let _ = tx.send(range);
+
+                let (status, offset) = match range {
+                    Some(offset) if ranges => ("206 Partial Content", offset as usize),
+                    _ => ("200 OK", 0),
+                };
+                let header = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len() - offset
+                );
+                // the client closes the connection once it has read enough
+                let _ = stream.write_all(header.as_bytes());
+                let _ = stream.write_all(&body[offset..]);
+            }
+        });
+        (url, rx)
+    }
+
+    fn episode(url: String, start_position: Duration) -> Station {
+        Station {
+            id: "podcast".to_string(),
+            name: "Episode".to_string(),
+            description: String::new(),
+            url,
+            image_file: Default::default(),
+            auto_restart: false,
+            auto_start: false,
+            gain_db: 0.0,
+            start_position,
+        }
+    }
+
+    #[test]
+    fn resumes_stream_using_range_request() {
+        let (url, ranges) = serve(mp3_file(400), true);
+        let mut station = episode(url, Duration::from_secs(5));
+
+        let mut source = Player::open_source(&mut station, None).unwrap();
+
+        assert_eq!(ranges.recv().unwrap(), None);
+        // the tag is skipped, then 16000 bytes per second at 128 kbit/s
+        assert_eq!(
+            ranges.recv().unwrap(),
+            Some(ID3_TAG_LEN as u64 + 5 * 16_000)
+        );
+        assert_eq!(station.start_position, Duration::from_secs(5));
+        assert!(source.next().is_some());
+    }
+
+    #[test]
+    fn plays_from_start_without_range_support() {
+        let (url, _ranges) = serve(mp3_file(400), false);
+        let mut station = episode(url, Duration::from_secs(5));
+
+        let mut source = Player::open_source(&mut station, None).unwrap();
+
+        assert_eq!(station.start_position, Duration::ZERO);
+        assert!(source.next().is_some());
+    }
+
+    #[test]
+    fn connects_again_when_preconnected_stream_has_failed() {
+        // nothing listens on the port once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let preconnection = Preconnection::open(
+            episode(format!("http://127.0.0.1:{port}/"), Duration::ZERO),
+            64 * 1024,
+            0,
+        );
+        while preconnection.is_connected() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let (url, ranges) = serve(mp3_file(400), false);
+        let mut station = episode(url, Duration::ZERO);
+
+        let mut source =
+            Player::open_source(&mut station, Some(preconnection.into_reader())).unwrap();
+
+        assert_eq!(ranges.recv().unwrap(), None);
+        assert!(source.next().is_some());
+    }
+
+    #[test]
+    fn seeks_in_local_mp3_file() {
+        let path = std::env::temp_dir().join(format!("radio-seek-{}.mp3", std::process::id()));
+        std::fs::write(&path, mp3_file(400)).unwrap();
+        let mut station = episode(
+            format!("{FILE_URL_PREFIX}{}", path.to_string_lossy()),
+            Duration::from_secs(5),
+        );
+
+        let source = Player::open_source(&mut station, None);
+        std::fs::remove_file(&path).unwrap();
+
+        // everything after the first 5 seconds is played, give or take the frame the seek ends in
+        let expected = 400 * SAMPLES_PER_FRAME - 5 * 44_100;
+        let samples = source.unwrap().count();
+        assert!(samples.abs_diff(expected) <= 2 * SAMPLES_PER_FRAME);
+    }
 }
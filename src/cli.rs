@@ -7,6 +7,10 @@ pub struct Args {
     #[clap(short, long, value_parser)]
     pub config_path: Option<String>,
 
+    /// Plays on `null`, `pipe` or `wav:<path>` instead of the output device of the settings
+    #[clap(short, long, value_parser)]
+    pub output: Option<String>,
+
     /// Subcommands
     #[clap(subcommand)]
     pub subcommand: Command,
@@ -4,16 +4,190 @@ use std::{
     fs::{self, File},
     io::Write,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use serde::{Deserialize, Serialize};
 
+/// Station IDs which are used internally for playback which is not based on a configured station.
+pub const RESERVED_STATION_IDS: [&str; 3] = ["url", "library", "podcast"];
+
+/// The name of the zone which always exists and which is used by the routes without a zone.
+pub const MAIN_ZONE: &str = "main";
+
 #[derive(Serialize, Deserialize)]
 pub struct Config {
     pub port: u16,
     pub session_key: String,
     pub users: Vec<User>,
     pub stations: Vec<Station>,
+    /// The duration of the crossfade when switching between stations
+    #[serde(default = "default_crossfade_ms")]
+    pub crossfade_ms: u64,
+    /// The duration of the fade when starting, stopping or changing the volume
+    #[serde(default = "default_fade_ms")]
+    pub fade_ms: u64,
+    /// The highest volume which can be set, values above 100 amplify the streams
+    #[serde(default = "default_max_volume_percent")]
+    pub max_volume_percent: u8,
+    #[serde(default)]
+    pub library: Option<LibraryConfig>,
+    #[serde(default)]
+    pub podcasts: Option<PodcastConfig>,
+    #[serde(default)]
+    pub zapping: Option<ZappingConfig>,
+    #[serde(default)]
+    pub normalization: Option<NormalizationConfig>,
+    #[serde(default)]
+    pub silence: Option<SilenceConfig>,
+    #[serde(default)]
+    pub pipe: Option<PipeConfig>,
+    #[serde(default)]
+    pub listen: Option<ListenConfig>,
+    #[serde(default)]
+    pub icecast: Option<IcecastConfig>,
+    /// Additional zones which play independently of the main zone
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+}
+
+fn default_crossfade_ms() -> u64 {
+    1500
+}
+
+fn default_fade_ms() -> u64 {
+    300
+}
+
+fn default_max_volume_percent() -> u8 {
+    100
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LibraryConfig {
+    pub path: PathBuf,
+    pub rescan_interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PodcastConfig {
+    pub cache_path: PathBuf,
+    pub refresh_interval_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NormalizationConfig {
+    /// The loudness all stations are adjusted to
+    pub target_lufs: f32,
+    /// The maximum amount of gain or attenuation applied by the normalization
+    pub max_gain_db: f32,
+    /// The duration over which the integrated loudness is measured
+    pub window_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SilenceConfig {
+    /// Audio below this level (in dBFS) is considered to be silent
+    pub threshold_db: f32,
+    /// How long a stream has to be silent until the `action` is taken
+    pub duration_secs: u64,
+    pub action: SilenceAction,
+    /// The ID of the station which is played if the action is `fallback`
+    #[serde(default)]
+    pub fallback_station: Option<String>,
+    /// A URL which receives a POST request for every incident
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SilenceAction {
+    /// Reconnects to the silent stream
+    Reconnect,
+    /// Switches to the fallback station
+    Fallback,
+    /// Only records the incident and notifies the webhook
+    None,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct PipeConfig {
+    /// The named pipe (FIFO) or file the audio is written to, `-` writes to stdout
+    pub path: PathBuf,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: PcmFormat,
+}
+
+/// The encoding of raw PCM samples.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PcmFormat {
+    S16le,
+    S16be,
+    S32le,
+    F32le,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ListenConfig {
+    /// The bitrate of the Opus stream which is sent to the listeners
+    pub bitrate_kbps: u32,
+}
+
+/// An Icecast server which receives the listen stream of a zone as a source.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IcecastConfig {
+    pub host: String,
+    pub port: u16,
+    /// The mount point the stream is published at, e.g. `/radio.opus`
+    pub mount: String,
+    #[serde(default = "default_icecast_username")]
+    pub username: String,
+    pub password: String,
+    /// The zone whose audio is sent to the server
+    #[serde(default = "default_icecast_zone")]
+    pub zone: String,
+    /// The name of the stream shown in the directory of the server
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    /// Whether the server may list the stream in public directories
+    #[serde(default)]
+    pub public: bool,
+}
+
+fn default_icecast_username() -> String {
+    "source".to_string()
+}
+
+fn default_icecast_zone() -> String {
+    MAIN_ZONE.to_string()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ZoneConfig {
+    pub name: String,
+    /// The ID of the station which is started in this zone on startup
+    #[serde(default)]
+    pub auto_start: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ZappingConfig {
+    /// Whether the neighbour stations are kept connected in the background
+    pub preconnect: bool,
+    /// The IDs of the stations to zap through, all stations are used if this is empty
+    #[serde(default)]
+    pub favourites: Vec<String>,
+    /// The maximum amount of data which is buffered per preconnected stream
+    pub buffer_kb: usize,
+    /// The maximum download rate per preconnected stream, 0 means unlimited
+    pub bandwidth_limit_kbps: u32,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -31,6 +205,12 @@ pub struct Station {
     pub image_file: PathBuf,
     pub auto_restart: bool,
     pub auto_start: bool,
+    /// A manual gain offset which is applied in addition to the loudness normalization
+    #[serde(default)]
+    pub gain_db: f32,
+    /// The position at which playback starts, only used for on-demand content
+    #[serde(skip)]
+    pub start_position: Duration,
 }
 
 impl Config {
@@ -47,9 +227,9 @@ impl Config {
         let mut auto_start_id = None;
 
         for station in &self.stations {
-            // check if station ID is `url`
-            if station.id == "url" {
-                bail!("station ID cannot be `url`")
+            // check if station ID is reserved
+            if RESERVED_STATION_IDS.contains(&station.id.as_str()) {
+                bail!("station ID cannot be `{}`: this ID is reserved", station.id)
             }
 
             // check if station ID is unique
@@ -82,6 +262,128 @@ impl Config {
                 )
             }
         }
+
+        if self.max_volume_percent == 0 {
+            bail!("invalid maximum volume: volume must be > 0%")
+        }
+
+        if let Some(library) = &self.library {
+            if !library.path.is_dir() {
+                bail!(
+                    "invalid library path `{}`: path is not a directory",
+                    library.path.to_string_lossy()
+                )
+            }
+            if library.rescan_interval_secs == 0 {
+                bail!("invalid library rescan interval: interval must be > 0 seconds")
+            }
+        }
+
+        if let Some(normalization) = &self.normalization {
+            if normalization.window_secs == 0 {
+                bail!("invalid normalization window: window must be > 0 seconds")
+            }
+            if normalization.max_gain_db < 0.0 {
+                bail!("invalid normalization maximum gain: gain must be >= 0 dB")
+            }
+        }
+
+        if let Some(zapping) = &self.zapping {
+            for id in &zapping.favourites {
+                if !station_ids.contains(id) {
+                    bail!("invalid zapping favourite `{id}`: this station ID does not exist")
+                }
+            }
+            if zapping.buffer_kb == 0 {
+                bail!("invalid zapping buffer size: buffer must be > 0 KB")
+            }
+        }
+
+        if let Some(silence) = &self.silence {
+            if silence.duration_secs == 0 {
+                bail!("invalid silence duration: duration must be > 0 seconds")
+            }
+            if silence.threshold_db > 0.0 {
+                bail!("invalid silence threshold: threshold must be <= 0 dBFS")
+            }
+            match &silence.fallback_station {
+                Some(id) if !station_ids.contains(id) => {
+                    bail!("invalid silence fallback station `{id}`: this station ID does not exist")
+                }
+                None if silence.action == SilenceAction::Fallback => {
+                    bail!("missing silence fallback station: required by the `fallback` action")
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(pipe) = &self.pipe {
+            if !(8_000..=384_000).contains(&pipe.sample_rate) {
+                bail!("invalid pipe sample rate: rate must be within 8000 and 384000 Hz")
+            }
+            if !(1..=8).contains(&pipe.channels) {
+                bail!("invalid pipe channels: there must be 1 to 8 channels")
+            }
+        }
+
+        if let Some(listen) = &self.listen {
+            if !(6..=510).contains(&listen.bitrate_kbps) {
+                bail!("invalid listen bitrate: bitrate must be within 6 and 510 kbps")
+            }
+        }
+
+        let mut zone_names = HashSet::from([MAIN_ZONE]);
+        for zone in &self.zones {
+            // the name is used in URLs and as the name of the settings file of the zone
+            if zone.name.is_empty()
+                || !zone
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!(
+                    "invalid zone name `{}`: only letters, digits, `-` and `_` are allowed",
+                    zone.name
+                )
+            }
+            if !zone_names.insert(zone.name.as_str()) {
+                bail!("duplicate zone name `{}`", zone.name)
+            }
+            if let Some(id) = &zone.auto_start {
+                if !station_ids.contains(id) {
+                    bail!(
+                        "invalid auto start station `{id}` of zone `{}`: this station ID does not exist",
+                        zone.name
+                    )
+                }
+            }
+        }
+
+        if let Some(icecast) = &self.icecast {
+            // the source client sends the stream which is encoded for the listeners
+            if self.listen.is_none() {
+                bail!("missing listen section: required by the icecast output")
+            }
+            if !icecast.mount.starts_with('/') {
+                bail!(
+                    "invalid icecast mount `{}`: mount must start with `/`",
+                    icecast.mount
+                )
+            }
+            if !zone_names.contains(icecast.zone.as_str()) {
+                bail!(
+                    "invalid icecast zone `{}`: this zone does not exist",
+                    icecast.zone
+                )
+            }
+        }
+
+        if let Some(podcasts) = &self.podcasts {
+            if podcasts.refresh_interval_secs == 0 {
+                bail!("invalid podcast refresh interval: interval must be > 0 seconds")
+            }
+        }
+
         Ok(())
     }
 }
@@ -1,6 +1,6 @@
 use minimp3::{Decoder, Frame};
 use std::fmt::Display;
-use std::io::Read;
+use std::io::{self, Cursor, Read};
 use std::time::Duration;
 
 use rodio::Source;
@@ -23,6 +23,9 @@ impl Display for Mp3Error {
     }
 }
 
+/// The size of the header of an ID3v2 tag.
+const ID3_HEADER_LEN: u64 = 10;
+
 /// This is a modified version of [rodio's Mp3Decoder](https://github.com/RustAudio/rodio/blob/55d957f8b40c59fccea4162c4b03f6dd87a7a4d9/src/decoder/mp3.rs)
 /// which removes the "Seek" trait bound for streaming network audio.
 ///
@@ -41,11 +44,12 @@ impl<R> Mp3StreamDecoder<R>
 where
     R: Read,
 {
-    pub fn new(mut data: R) -> Result<Self, Mp3Error> {
-        match is_mp3(data.by_ref()) {
+    pub fn new(data: R) -> Result<Self, Mp3Error> {
+        // the decoder which probes the stream keeps decoding it, so that no data is lost
+        let mut decoder = Decoder::new(data);
+        match decoder.next_frame() {
             Ok(current_frame) => {
                 debug!("Stream is valid MP3, starting decoder");
-                let decoder = Decoder::new(data);
 
                 Ok(Self {
                     decoder,
@@ -120,3 +124,49 @@ where
         Err(_) => Err(()),
     }
 }
+
+/// Returns the byte offset of the audio at `position` in the MP3 `data`.
+/// The offset is derived from the bitrate of the first frame, which is exact for constant bitrate
+/// streams and an estimate for variable bitrate streams. An ID3v2 tag in front of the audio is
+/// taken into account.
+pub fn byte_offset<R>(mut data: R, position: Duration) -> Result<u64, Mp3Error>
+where
+    R: Read,
+{
+    let mut header = [0; ID3_HEADER_LEN as usize];
+    data.read_exact(&mut header).map_err(|_| Mp3Error::NotMp3)?;
+    let tag_len = id3_tag_len(&header);
+    let frame = match tag_len {
+        // without a tag, the header is the start of the first frame
+        0 => is_mp3(Cursor::new(header).chain(data)),
+        len => {
+            io::copy(
+                &mut data.by_ref().take(len - ID3_HEADER_LEN),
+                &mut io::sink(),
+            )
+            .map_err(|_| Mp3Error::NotMp3)?;
+            is_mp3(data)
+        }
+    }
+    .map_err(|_| Mp3Error::NotMp3)?;
+
+    // one kbit/s is 125 bytes per second
+    Ok(tag_len + frame.bitrate as u64 * 125 * position.as_millis() as u64 / 1000)
+}
+
+/// Returns the size of the ID3v2 tag which starts with the `header`, 0 if there is none.
+fn id3_tag_len(header: &[u8; ID3_HEADER_LEN as usize]) -> u64 {
+    if !header.starts_with(b"ID3") {
+        return 0;
+    }
+    // the size is a syncsafe integer which uses 7 bits per byte
+    let size = header[6..]
+        .iter()
+        .fold(0, |size, byte| size << 7 | (byte & 0x7f) as u64);
+    // the tag may be followed by a footer which has the size of the header
+    let footer = match header[5] & 0x10 {
+        0 => 0,
+        _ => ID3_HEADER_LEN,
+    };
+    ID3_HEADER_LEN + size + footer
+}
@@ -0,0 +1,107 @@
+use std::f64::consts::PI;
+
+/// A second order IIR filter (transposed direct form II).
+#[derive(Clone, Copy)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    /// Creates a filter from coefficients which are already normalized by `a0`.
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b0: b[0],
+            b1: b[1],
+            b2: b[2],
+            a1: a[0],
+            a2: a[1],
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Self {
+        Self::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+
+    // The following filter designs are based on the "Audio EQ Cookbook" by Robert Bristow-Johnson.
+
+    pub fn peaking(sample_rate: u32, freq: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::omega(sample_rate, freq, q);
+        Self::normalized(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    pub fn low_shelf(sample_rate: u32, freq: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::omega(sample_rate, freq, q);
+        let sqrt_a = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + sqrt_a,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - sqrt_a,
+            ],
+        )
+    }
+
+    pub fn high_shelf(sample_rate: u32, freq: f64, q: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let (cos, alpha) = Self::omega(sample_rate, freq, q);
+        let sqrt_a = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a,
+            ],
+        )
+    }
+
+    fn omega(sample_rate: u32, freq: f64, q: f64) -> (f64, f64) {
+        // keep the frequency below nyquist, otherwise the filter becomes unstable
+        let freq = freq.min(sample_rate as f64 * 0.49);
+        let w0 = 2.0 * PI * freq / sample_rate as f64;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    /// Replaces the coefficients with the ones of `other` while keeping the filter state.
+    /// This avoids clicks when the filter is changed during playback.
+    pub fn set_coefficients(&mut self, other: &Biquad) {
+        self.b0 = other.b0;
+        self.b1 = other.b1;
+        self.b2 = other.b2;
+        self.a1 = other.a1;
+        self.a2 = other.a2;
+    }
+
+    #[inline]
+    pub fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
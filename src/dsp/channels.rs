@@ -0,0 +1,271 @@
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The number of device channels the stereo channels can be routed to.
+pub const MAX_DEVICE_CHANNELS: u16 = 32;
+
+/// Determines how the left and right channel are played on the output device.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ChannelSettings {
+    /// Shifts the stereo image from `-1.0` (left only) to `1.0` (right only)
+    #[serde(default)]
+    pub balance: f32,
+    /// Mixes both channels down to mono
+    #[serde(default)]
+    pub mono: bool,
+    /// Swaps the left and the right channel
+    #[serde(default)]
+    pub swap: bool,
+    /// The device channels (starting at 0) the left and the right channel are played on.
+    /// All other channels of the device remain silent.
+    /// If empty, the left and the right channel are played on the first two device channels.
+    #[serde(default)]
+    pub routing: Vec<u16>,
+}
+
+impl ChannelSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-1.0..=1.0).contains(&self.balance) {
+            return Err(format!(
+                "balance {} is not within -1.0 and 1.0",
+                self.balance
+            ));
+        }
+        if self.routing.len() > 2 {
+            return Err("at most two device channels (left and right) can be routed".to_string());
+        }
+        if let Some(channel) = self.routing.iter().find(|c| **c >= MAX_DEVICE_CHANNELS) {
+            return Err(format!("device channel {channel} is out of range"));
+        }
+        Ok(())
+    }
+
+    /// Returns whether the channels are played unchanged.
+    pub fn is_passthrough(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns whether both channels are routed to the same device channel,
+    /// which adds up their levels.
+    pub fn boosts(&self) -> bool {
+        matches!(self.routing.as_slice(), [left, right] if left == right)
+    }
+}
+
+/// Applies the [`ChannelSettings`] to the wrapped source.
+/// The source is treated as stereo: mono sources are duplicated to both channels,
+/// only the first two channels of sources with more channels are used.
+pub struct ChannelMapper<S>
+where
+    S: Source<Item = f32>,
+{
+    inner: S,
+    settings: ChannelSettings,
+    /// The number of channels the mapped source is played on
+    output_channels: u16,
+
+    /// The samples of the current output frame
+    frame: Vec<f32>,
+    frame_pos: usize,
+}
+
+impl<S> ChannelMapper<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Wraps the `inner` source for an output device with `device_channels` channels.
+    pub fn new(inner: S, settings: ChannelSettings, device_channels: u16) -> Self {
+        let output_channels = match settings.routing.is_empty() {
+            true => 2,
+            false => device_channels.max(1),
+        };
+        for channel in settings.routing.iter().filter(|c| **c >= output_channels) {
+            warn!("Cannot route to device channel {channel}: the device has {output_channels} channels");
+        }
+
+        Self {
+            inner,
+            settings,
+            output_channels,
+            frame: vec![0.0; output_channels as usize],
+            frame_pos: output_channels as usize,
+        }
+    }
+
+    /// Reads the next input frame and maps it to an output frame.
+    /// Returns `false` if the inner source has ended.
+    fn next_frame(&mut self) -> bool {
+        let channels = self.inner.channels().max(1);
+        let Some(first) = self.inner.next() else {
+            return false;
+        };
+        let second = match channels {
+            1 => first,
+            _ => self.inner.next().unwrap_or(first),
+        };
+        for _ in 2..channels {
+            self.inner.next();
+        }
+
+        let (mut left, mut right) = match self.settings.swap {
+            true => (second, first),
+            false => (first, second),
+        };
+        if self.settings.mono {
+            left = (left + right) / 2.0;
+            right = left;
+        }
+        left *= (1.0 - self.settings.balance).min(1.0);
+        right *= (1.0 + self.settings.balance).min(1.0);
+
+        self.frame.iter_mut().for_each(|s| *s = 0.0);
+        match self.settings.routing.as_slice() {
+            [] => {
+                self.frame[0] = left;
+                self.frame[1] = right;
+            }
+            routing => {
+                for (channel, sample) in routing.iter().zip([left, right]) {
+                    if let Some(output) = self.frame.get_mut(*channel as usize) {
+                        *output += sample;
+                    }
+                }
+            }
+        }
+        self.frame_pos = 0;
+        true
+    }
+}
+
+impl<S> Iterator for ChannelMapper<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.frame_pos == self.frame.len() && !self.next_frame() {
+            return None;
+        }
+        let sample = self.frame[self.frame_pos];
+        self.frame_pos += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for ChannelMapper<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        // the samples of the current output frame were already taken from the inner source
+        let buffered = self.frame.len() - self.frame_pos;
+        let channels = self.inner.channels().max(1) as usize;
+        self.inner
+            .current_frame_len()
+            .map(|len| len / channels * self.output_channels as usize + buffered)
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.output_channels
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    /// Maps a stereo frame with the left channel at 0.8 and the right one at 0.2.
+    fn map(settings: ChannelSettings, device_channels: u16) -> Vec<f32> {
+        let source = SamplesBuffer::new(2, 48_000, vec![0.8, 0.2]);
+        ChannelMapper::new(source, settings, device_channels).collect()
+    }
+
+    #[test]
+    fn passes_stereo_through() {
+        assert_eq!(map(ChannelSettings::default(), 2), [0.8, 0.2]);
+    }
+
+    #[test]
+    fn applies_balance() {
+        let left = ChannelSettings {
+            balance: -0.5,
+            ..Default::default()
+        };
+        assert_eq!(map(left, 2), [0.8, 0.1]);
+
+        let right = ChannelSettings {
+            balance: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(map(right, 2), [0.0, 0.2]);
+    }
+
+    #[test]
+    fn mixes_down_to_mono() {
+        let mono = ChannelSettings {
+            mono: true,
+            ..Default::default()
+        };
+        assert_eq!(map(mono, 2), [0.5, 0.5]);
+    }
+
+    #[test]
+    fn swaps_channels() {
+        let swap = ChannelSettings {
+            swap: true,
+            ..Default::default()
+        };
+        assert_eq!(map(swap, 2), [0.2, 0.8]);
+    }
+
+    #[test]
+    fn routes_to_device_channels() {
+        let routed = ChannelSettings {
+            routing: vec![3, 1],
+            ..Default::default()
+        };
+        assert_eq!(map(routed, 4), [0.0, 0.2, 0.0, 0.8]);
+
+        // both channels on the same device channel add up
+        let summed = ChannelSettings {
+            routing: vec![2, 2],
+            ..Default::default()
+        };
+        assert_eq!(map(summed, 3), [0.0, 0.0, 1.0]);
+
+        // channels the device does not have are dropped
+        let missing = ChannelSettings {
+            routing: vec![0, 5],
+            ..Default::default()
+        };
+        assert_eq!(map(missing, 2), [0.8, 0.0]);
+    }
+
+    #[test]
+    fn duplicates_mono_sources() {
+        let source = SamplesBuffer::new(1, 48_000, vec![0.4]);
+        let swap = ChannelSettings {
+            swap: true,
+            ..Default::default()
+        };
+        let mapped: Vec<f32> = ChannelMapper::new(source, swap, 2).collect();
+        assert_eq!(mapped, [0.4, 0.4]);
+    }
+}
@@ -0,0 +1,369 @@
+use chrono::NaiveTime;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use super::{db_to_linear, limiter::Limiter};
+
+const SCHEDULE_TIME_FORMAT: &str = "%H:%M";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressorPreset {
+    #[default]
+    Off,
+    Light,
+    Night,
+}
+
+/// Activates a preset during a time of day, e.g. from `22:00` until `07:00`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CompressorSchedule {
+    pub start: String,
+    pub end: String,
+    pub preset: CompressorPreset,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct CompressorSettings {
+    /// The preset which is used outside of the schedule
+    pub preset: CompressorPreset,
+    pub schedule: Option<CompressorSchedule>,
+}
+
+struct Parameters {
+    threshold_db: f32,
+    ratio: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    makeup_db: f32,
+    /// The level which the output of the limiter never exceeds
+    ceiling_db: f32,
+}
+
+impl CompressorPreset {
+    fn parameters(self) -> Option<Parameters> {
+        match self {
+            CompressorPreset::Off => None,
+            CompressorPreset::Light => Some(Parameters {
+                threshold_db: -18.0,
+                ratio: 2.0,
+                attack_ms: 10.0,
+                release_ms: 200.0,
+                makeup_db: 3.0,
+                ceiling_db: -0.5,
+            }),
+            CompressorPreset::Night => Some(Parameters {
+                threshold_db: -32.0,
+                ratio: 6.0,
+                attack_ms: 5.0,
+                release_ms: 300.0,
+                makeup_db: 12.0,
+                ceiling_db: -1.0,
+            }),
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => CompressorPreset::Light,
+            2 => CompressorPreset::Night,
+            _ => CompressorPreset::Off,
+        }
+    }
+}
+
+impl CompressorSchedule {
+    fn times(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time, SCHEDULE_TIME_FORMAT)
+                .map_err(|_| format!("invalid time `{time}`: expected `HH:MM`"))
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    /// Returns whether the schedule is active at the given time of day.
+    /// Schedules may span midnight.
+    fn is_active(&self, now: NaiveTime) -> bool {
+        let Ok((start, end)) = self.times() else {
+            return false;
+        };
+        match start <= end {
+            true => start <= now && now < end,
+            false => now >= start || now < end,
+        }
+    }
+}
+
+impl CompressorSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.schedule {
+            Some(schedule) => schedule.times().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the preset which is active at the given time of day.
+    pub fn active_preset(&self, now: NaiveTime) -> CompressorPreset {
+        match &self.schedule {
+            Some(schedule) if schedule.is_active(now) => schedule.preset,
+            _ => self.preset,
+        }
+    }
+}
+
+/// Allows switching the compressor preset of a running stream.
+pub struct CompressorControl {
+    preset: AtomicU8,
+}
+
+impl CompressorControl {
+    pub fn new(preset: CompressorPreset) -> Self {
+        Self {
+            preset: AtomicU8::new(preset as u8),
+        }
+    }
+
+    pub fn preset(&self) -> CompressorPreset {
+        CompressorPreset::from_u8(self.preset.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, preset: CompressorPreset) {
+        self.preset.store(preset as u8, Ordering::Relaxed)
+    }
+}
+
+/// A feed-forward compressor followed by a look-ahead limiter.
+pub struct Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    limiter: Limiter<Compression<S>>,
+    control: Arc<CompressorControl>,
+    preset: CompressorPreset,
+}
+
+impl<S> Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S, control: Arc<CompressorControl>) -> Self {
+        let preset = control.preset();
+        Self {
+            limiter: Limiter::with_ceiling(
+                Compression::new(inner, control.clone()),
+                ceiling_db(preset),
+            ),
+            control,
+            preset,
+        }
+    }
+}
+
+/// Returns the ceiling of the limiter for the preset, it is only active with the compressor.
+fn ceiling_db(preset: CompressorPreset) -> f64 {
+    preset
+        .parameters()
+        .map_or(f64::INFINITY, |params| params.ceiling_db as f64)
+}
+
+impl<S> Iterator for Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        let preset = self.control.preset();
+        if preset != self.preset {
+            self.preset = preset;
+            self.limiter.set_ceiling(ceiling_db(preset));
+        }
+        self.limiter.next()
+    }
+}
+
+impl<S> Source for Compressor<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.limiter.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.limiter.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.limiter.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.limiter.total_duration()
+    }
+}
+
+/// The gain stage of the compressor.
+/// The gain is computed from the loudest channel so that the stereo image is preserved.
+struct Compression<S>
+where
+    S: Source<Item = f32>,
+{
+    inner: S,
+    control: Arc<CompressorControl>,
+    preset: CompressorPreset,
+    parameters: Option<Parameters>,
+
+    /// The samples of the current frame
+    frame: Vec<f32>,
+    frame_pos: usize,
+    /// The current gain reduction in dB (always <= 0)
+    reduction_db: f32,
+}
+
+impl<S> Compression<S>
+where
+    S: Source<Item = f32>,
+{
+    fn new(inner: S, control: Arc<CompressorControl>) -> Self {
+        let preset = control.preset();
+        Self {
+            inner,
+            control,
+            preset,
+            parameters: preset.parameters(),
+            frame: vec![],
+            frame_pos: 0,
+            reduction_db: 0.0,
+        }
+    }
+
+    /// Reads the next frame (one sample per channel) and applies the gain to it.
+    fn process_frame(&mut self) {
+        let preset = self.control.preset();
+        if preset != self.preset {
+            self.preset = preset;
+            self.parameters = preset.parameters();
+        }
+
+        let channels = self.inner.channels().max(1) as usize;
+        let sample_rate = self.inner.sample_rate().max(1) as f32;
+
+        self.frame.clear();
+        self.frame_pos = 0;
+        self.frame.extend(self.inner.by_ref().take(channels));
+
+        let Some(params) = &self.parameters else {
+            self.reduction_db = 0.0;
+            return;
+        };
+
+        let peak = self.frame.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let level_db = 20.0 * peak.max(1e-6).log10();
+
+        let target_db = match level_db > params.threshold_db {
+            true => (params.threshold_db - level_db) * (1.0 - 1.0 / params.ratio),
+            false => 0.0,
+        };
+        let time_ms = match target_db < self.reduction_db {
+            true => params.attack_ms,
+            false => params.release_ms,
+        };
+        let coeff = 1.0 - (-1000.0 / (time_ms * sample_rate)).exp();
+        self.reduction_db += (target_db - self.reduction_db) * coeff;
+
+        let gain = db_to_linear((self.reduction_db + params.makeup_db) as f64) as f32;
+        self.frame.iter_mut().for_each(|s| *s *= gain);
+    }
+}
+
+impl<S> Iterator for Compression<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.frame_pos == self.frame.len() {
+            self.process_frame();
+        }
+        let sample = self.frame.get(self.frame_pos).copied()?;
+        self.frame_pos += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Compression<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        // the samples of the current frame were already taken from the inner source
+        let buffered = self.frame.len() - self.frame_pos;
+        self.inner.current_frame_len().map(|len| len + buffered)
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    /// Returns a sine at 100 Hz with the amplitude.
+    fn sine(amplitude: f32, duration: Duration) -> impl Iterator<Item = f32> {
+        let samples = (SAMPLE_RATE as f32 * duration.as_secs_f32()) as usize;
+        (0..samples).map(move |i| {
+            amplitude * (2.0 * std::f32::consts::PI * 100.0 * i as f32 / SAMPLE_RATE as f32).sin()
+        })
+    }
+
+    #[test]
+    fn ceiling_does_not_flatten_sudden_peaks() {
+        // the compressor needs a few milliseconds until it reduces the gain of the loud part
+        let input: Vec<_> = sine(0.01, Duration::from_millis(500))
+            .chain(sine(1.0, Duration::from_millis(500)))
+            .collect();
+        let control = Arc::new(CompressorControl::new(CompressorPreset::Night));
+        let output: Vec<_> =
+            Compressor::new(SamplesBuffer::new(1, SAMPLE_RATE, input), control).collect();
+
+        let ceiling = db_to_linear(-1.0) as f32;
+        assert!(output.iter().all(|s| s.abs() <= ceiling));
+        // the gain is reduced ahead of the peaks instead of cutting them off at the ceiling,
+        // only the first peak of the burst arrives before the gain has been reduced entirely
+        let clipped = output.iter().filter(|s| s.abs() == ceiling).count();
+        assert!(clipped < 20, "{clipped} samples are cut off at the ceiling");
+    }
+}
@@ -0,0 +1,304 @@
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use super::biquad::Biquad;
+
+pub const MAX_BANDS: usize = 16;
+pub const MIN_FREQUENCY: f32 = 20.0;
+pub const MAX_FREQUENCY: f32 = 20_000.0;
+pub const MIN_Q: f32 = 0.1;
+pub const MAX_Q: f32 = 10.0;
+pub const MAX_GAIN_DB: f32 = 24.0;
+
+/// The center frequencies of the bands of the `graphic` preset.
+const GRAPHIC_FREQUENCIES: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BandKind {
+    LowShelf,
+    Peaking,
+    HighShelf,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct EqualizerBand {
+    pub kind: BandKind,
+    /// The center (or corner) frequency in Hz
+    pub frequency: f32,
+    pub q: f32,
+    /// The gain in dB
+    pub gain: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct EqualizerSettings {
+    pub enabled: bool,
+    /// The name of the preset the bands are based on
+    pub preset: Option<String>,
+    pub bands: Vec<EqualizerBand>,
+}
+
+impl EqualizerBand {
+    fn filter(&self, sample_rate: u32) -> Biquad {
+        let (freq, q, gain) = (self.frequency as f64, self.q as f64, self.gain as f64);
+        match self.kind {
+            BandKind::LowShelf => Biquad::low_shelf(sample_rate, freq, q, gain),
+            BandKind::Peaking => Biquad::peaking(sample_rate, freq, q, gain),
+            BandKind::HighShelf => Biquad::high_shelf(sample_rate, freq, q, gain),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&self.frequency) {
+            return Err(format!(
+                "band frequency must be between {MIN_FREQUENCY} and {MAX_FREQUENCY} Hz"
+            ));
+        }
+        if !(MIN_Q..=MAX_Q).contains(&self.q) {
+            return Err(format!("band Q must be between {MIN_Q} and {MAX_Q}"));
+        }
+        if self.gain.abs() > MAX_GAIN_DB {
+            return Err(format!(
+                "band gain must be between -{MAX_GAIN_DB} and {MAX_GAIN_DB} dB"
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl EqualizerSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bands.len() > MAX_BANDS {
+            return Err(format!("at most {MAX_BANDS} bands are supported"));
+        }
+        self.bands.iter().try_for_each(|band| band.validate())
+    }
+}
+
+const fn band(kind: BandKind, frequency: f32, q: f32, gain: f32) -> EqualizerBand {
+    EqualizerBand {
+        kind,
+        frequency,
+        q,
+        gain,
+    }
+}
+
+/// Returns the names of all built-in presets.
+pub fn preset_names() -> Vec<&'static str> {
+    vec![
+        "flat",
+        "graphic",
+        "bass_cut",
+        "treble_boost",
+        "small_speakers",
+        "loudness",
+        "speech",
+    ]
+}
+
+/// Returns the bands of the built-in preset with the given name.
+pub fn preset(name: &str) -> Option<Vec<EqualizerBand>> {
+    let bands = match name {
+        "flat" => vec![],
+        // a classic 10 band graphic equalizer, all bands start out at 0 dB
+        "graphic" => GRAPHIC_FREQUENCIES
+            .iter()
+            .map(|&freq| band(BandKind::Peaking, freq, 1.41, 0.0))
+            .collect(),
+        "bass_cut" => vec![band(BandKind::LowShelf, 150.0, 0.71, -8.0)],
+        "treble_boost" => vec![band(BandKind::HighShelf, 6000.0, 0.71, 4.0)],
+        "small_speakers" => vec![
+            band(BandKind::LowShelf, 120.0, 0.71, -10.0),
+            band(BandKind::Peaking, 400.0, 1.0, -2.0),
+            band(BandKind::HighShelf, 5000.0, 0.71, 4.0),
+        ],
+        "loudness" => vec![
+            band(BandKind::LowShelf, 100.0, 0.71, 5.0),
+            band(BandKind::HighShelf, 10000.0, 0.71, 3.0),
+        ],
+        "speech" => vec![
+            band(BandKind::LowShelf, 200.0, 0.71, -8.0),
+            band(BandKind::Peaking, 3000.0, 1.0, 3.0),
+        ],
+        _ => return None,
+    };
+    Some(bands)
+}
+
+/// Allows changing the equalizer of a running stream.
+pub struct EqualizerControl {
+    settings: Mutex<EqualizerSettings>,
+    version: AtomicU64,
+}
+
+impl EqualizerControl {
+    pub fn new(settings: EqualizerSettings) -> Self {
+        Self {
+            settings: Mutex::new(settings),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    pub fn settings(&self) -> EqualizerSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, settings: EqualizerSettings) {
+        *self.settings.lock().unwrap() = settings;
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Returns whether an enabled band raises the level.
+    pub fn boosts(&self) -> bool {
+        let settings = self.settings.lock().unwrap();
+        settings.enabled && settings.bands.iter().any(|band| band.gain > 0.0)
+    }
+}
+
+/// Applies the bands of the equalizer to the wrapped source.
+/// Changes to the [`EqualizerControl`] are picked up while the source is playing.
+pub struct Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    inner: S,
+    control: Arc<EqualizerControl>,
+    version: u64,
+    enabled: bool,
+    bands: Vec<EqualizerBand>,
+
+    sample_rate: u32,
+    channels: u16,
+    /// The filters of every band for each channel
+    filters: Vec<Vec<Biquad>>,
+    channel: usize,
+}
+
+impl<S> Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S, control: Arc<EqualizerControl>) -> Self {
+        let mut equalizer = Self {
+            inner,
+            version: control.version.load(Ordering::Acquire),
+            control,
+            enabled: false,
+            bands: vec![],
+            sample_rate: 0,
+            channels: 0,
+            filters: vec![],
+            channel: 0,
+        };
+        equalizer.load_settings();
+        equalizer.reset_format();
+        equalizer
+    }
+
+    fn load_settings(&mut self) {
+        let settings = self.control.settings();
+        self.enabled = settings.enabled;
+        self.bands = settings.bands;
+    }
+
+    /// (Re)creates the filters for the current sample rate and channel count of the source.
+    fn reset_format(&mut self) {
+        self.sample_rate = self.inner.sample_rate().max(1);
+        self.channels = self.inner.channels().max(1);
+        let filters: Vec<Biquad> = self
+            .bands
+            .iter()
+            .map(|band| band.filter(self.sample_rate))
+            .collect();
+        self.filters = vec![filters; self.channels as usize];
+        self.channel = 0;
+    }
+
+    /// Applies changed settings, keeping the filter state if the number of bands is unchanged.
+    fn update_settings(&mut self) {
+        let band_count = self.bands.len();
+        self.load_settings();
+
+        if self.bands.len() != band_count {
+            self.reset_format();
+            return;
+        }
+        for channel in self.filters.iter_mut() {
+            for (filter, band) in channel.iter_mut().zip(&self.bands) {
+                filter.set_coefficients(&band.filter(self.sample_rate));
+            }
+        }
+    }
+}
+
+impl<S> Iterator for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            let version = self.control.version.load(Ordering::Acquire);
+            if version != self.version {
+                self.version = version;
+                self.update_settings();
+            }
+            if self.inner.sample_rate() != self.sample_rate
+                || self.inner.channels() != self.channels
+            {
+                self.reset_format();
+            }
+        }
+
+        let sample = self.inner.next()?;
+
+        let filters = &mut self.filters[self.channel];
+        self.channel = (self.channel + 1) % self.channels as usize;
+
+        if !self.enabled {
+            return Some(sample);
+        }
+        let output = filters
+            .iter_mut()
+            .fold(sample as f64, |acc, filter| filter.process(acc));
+        Some(output as f32)
+    }
+}
+
+impl<S> Source for Equalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
@@ -0,0 +1,271 @@
+use rodio::Source;
+use std::{
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::db_to_linear;
+
+/// The level below which a fade is considered to be silent.
+/// Ramps start and end at this level instead of zero since zero cannot be expressed in dB.
+const SILENCE_DB: f32 = -60.0;
+const SILENCE_POLL_INTERVAL_MS: u64 = 10;
+
+/// Allows fading the output of a running stream.
+pub struct GainControl {
+    /// The gain which is faded to (stored as `f32` bits)
+    target: AtomicU32,
+    fade_ms: AtomicU64,
+    /// Incremented whenever a new fade is started
+    version: AtomicU64,
+    /// The gain which was last applied by the stream (stored as `f32` bits)
+    current: AtomicU32,
+}
+
+impl GainControl {
+    pub fn new(gain: f32) -> Self {
+        Self {
+            target: AtomicU32::new(gain.to_bits()),
+            fade_ms: AtomicU64::new(0),
+            version: AtomicU64::new(0),
+            current: AtomicU32::new(gain.to_bits()),
+        }
+    }
+
+    /// Fades from the current gain to the `target` gain over the given `duration`.
+    pub fn fade_to(&self, target: f32, duration: Duration) {
+        self.target.store(target.to_bits(), Ordering::Relaxed);
+        self.fade_ms
+            .store(duration.as_millis() as u64, Ordering::Relaxed);
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn current(&self) -> f32 {
+        f32::from_bits(self.current.load(Ordering::Relaxed))
+    }
+
+    /// Returns whether the gain is above unity or is fading to a gain above unity.
+    pub fn boosts(&self) -> bool {
+        self.current() > 1.0 || f32::from_bits(self.target.load(Ordering::Relaxed)) > 1.0
+    }
+
+    /// Blocks until the stream has faded to silence or the `timeout` has elapsed.
+    /// The timeout prevents waiting forever if the stream is no longer consumed.
+    pub fn wait_for_silence(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.current() > 0.0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(SILENCE_POLL_INTERVAL_MS));
+        }
+    }
+}
+
+/// Applies the gain of a [`GainControl`] to every sample.
+/// Fades are interpolated per frame along a logarithmic curve,
+/// so that they are perceived as even and free of zipper noise.
+pub struct GainRamp<S>
+where
+    S: Source<Item = f32>,
+{
+    inner: S,
+    control: Arc<GainControl>,
+    version: u64,
+
+    gain: f32,
+    gain_db: f32,
+    step_db: f32,
+    target: f32,
+    remaining_frames: u64,
+    /// The position within the current frame
+    channel: u16,
+}
+
+impl<S> GainRamp<S>
+where
+    S: Source<Item = f32>,
+{
+    /// Wraps the `inner` source, starting out at the current gain of the `control`.
+    pub fn new(inner: S, control: Arc<GainControl>) -> Self {
+        let gain = control.current();
+        Self {
+            inner,
+            control,
+            // the pending fade (if any) is picked up by the first frame
+            version: u64::MAX,
+            gain,
+            gain_db: to_db(gain),
+            step_db: 0.0,
+            target: gain,
+            remaining_frames: 0,
+            channel: 0,
+        }
+    }
+
+    fn start_fade(&mut self) {
+        self.target = f32::from_bits(self.control.target.load(Ordering::Relaxed));
+        let fade_ms = self.control.fade_ms.load(Ordering::Relaxed);
+        let frames = self.inner.sample_rate() as u64 * fade_ms / 1000;
+
+        self.gain_db = to_db(self.gain);
+        self.remaining_frames = frames;
+        self.step_db = match frames {
+            0 => 0.0,
+            _ => (to_db(self.target) - self.gain_db) / frames as f32,
+        };
+    }
+
+    /// Advances the fade by one frame.
+    fn advance(&mut self) {
+        let version = self.control.version.load(Ordering::Acquire);
+        if version != self.version {
+            self.version = version;
+            self.start_fade();
+        }
+
+        match self.remaining_frames {
+            0 => self.gain = self.target,
+            1 => {
+                // end exactly at the target, this also takes care of fading to (or from) silence
+                self.gain = self.target;
+                self.remaining_frames = 0;
+            }
+            _ => {
+                self.gain_db += self.step_db;
+                self.gain = db_to_linear(self.gain_db as f64) as f32;
+                self.remaining_frames -= 1;
+            }
+        }
+        self.control
+            .current
+            .store(self.gain.to_bits(), Ordering::Relaxed);
+    }
+}
+
+fn to_db(gain: f32) -> f32 {
+    match gain > 0.0 {
+        true => (20.0 * gain.log10()).max(SILENCE_DB),
+        false => SILENCE_DB,
+    }
+}
+
+impl<S> Iterator for GainRamp<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+
+        if self.channel == 0 {
+            self.advance();
+        }
+        self.channel += 1;
+        if self.channel >= self.inner.channels() {
+            self.channel = 0;
+        }
+
+        Some(sample * self.gain)
+    }
+}
+
+impl<S> Source for GainRamp<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    /// One frame per millisecond, so that a fade of `n` ms lasts `n` frames.
+    const SAMPLE_RATE: u32 = 1000;
+
+    /// Returns a ramp over a constant source, so that every sample is the gain of its frame.
+    fn ramp(
+        gain: f32,
+        channels: u16,
+        frames: usize,
+    ) -> (GainRamp<SamplesBuffer<f32>>, Arc<GainControl>) {
+        let control = Arc::new(GainControl::new(gain));
+        let source =
+            SamplesBuffer::new(channels, SAMPLE_RATE, vec![1.0; frames * channels as usize]);
+        (GainRamp::new(source, control.clone()), control)
+    }
+
+    #[test]
+    fn ends_exactly_at_the_target() {
+        let (mut ramp, control) = ramp(1.0, 2, 200);
+        control.fade_to(0.5, Duration::from_millis(100));
+        let samples: Vec<f32> = ramp.by_ref().take(200).collect();
+
+        assert!(samples[0] < 1.0 && samples[0] > 0.99);
+        // both channels of a frame get the same gain
+        assert_eq!(samples[0], samples[1]);
+        assert!(samples[197] > 0.5);
+        assert!(samples[198..].iter().all(|s| *s == 0.5));
+        assert_eq!(control.current(), 0.5);
+
+        control.fade_to(0.0, Duration::from_millis(50));
+        let samples: Vec<f32> = ramp.collect();
+        assert!(samples[97] > 0.0);
+        assert!(samples[98..].iter().all(|s| *s == 0.0));
+        assert_eq!(control.current(), 0.0);
+    }
+
+    #[test]
+    fn fades_monotonically() {
+        let (ramp, control) = ramp(1.0, 1, 1000);
+        control.fade_to(0.0, Duration::from_millis(1000));
+        let samples: Vec<f32> = ramp.collect();
+
+        assert!(samples.windows(2).all(|w| w[1] < w[0] || w[1] == 0.0));
+        assert_eq!(samples[999], 0.0);
+
+        // the curve is logarithmic, so halfway through the level is far below half the gain
+        assert!(samples[500] < 0.1);
+    }
+
+    #[test]
+    fn changes_target_mid_ramp() {
+        let (mut ramp, control) = ramp(1.0, 1, 300);
+        control.fade_to(0.1, Duration::from_millis(100));
+        let fade_out: Vec<f32> = ramp.by_ref().take(50).collect();
+        let reached = *fade_out.last().unwrap();
+        assert!(reached < 1.0 && reached > 0.1);
+
+        control.fade_to(1.0, Duration::from_millis(100));
+        let fade_in: Vec<f32> = ramp.collect();
+
+        // the new fade starts at the gain which was reached instead of jumping
+        assert!(fade_in[0] > reached && fade_in[0] - reached < 0.01);
+        assert!(fade_in.windows(2).take(99).all(|w| w[1] > w[0]));
+        assert!(fade_in[99..].iter().all(|s| *s == 1.0));
+    }
+}
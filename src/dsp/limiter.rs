@@ -0,0 +1,276 @@
+use rodio::Source;
+use std::{collections::VecDeque, time::Duration};
+
+use super::db_to_linear;
+
+/// The level which the output of the limiter never exceeds.
+const LIMITER_CEILING_DB: f64 = -0.3;
+/// How far the limiter looks ahead, this is also the latency it adds.
+const LIMITER_LOOKAHEAD_MS: u32 = 5;
+const LIMITER_RELEASE_MS: f32 = 150.0;
+
+/// A look-ahead limiter which prevents the wrapped source from clipping.
+/// The gain is reduced smoothly before a peak is reached, so that the peak passes the ceiling
+/// without being clipped. The gain reduction is linked across all channels.
+pub struct Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    inner: S,
+    ceiling: f32,
+    /// Decides whether the limiter is engaged, it always is if unset
+    engaged: Option<Box<dyn Fn() -> bool + Send>>,
+    active: bool,
+    lookahead_frames: usize,
+    attack: f32,
+    release: f32,
+
+    /// The samples which have been read from the inner source but have not been played yet
+    delay: VecDeque<f32>,
+    /// The gains which are required by the delayed frames (frame index, gain),
+    /// kept in increasing order so that the front is the minimum of the look-ahead window
+    required: VecDeque<(u64, f32)>,
+    frames_read: u64,
+    frames_played: u64,
+    gain: f32,
+
+    /// The samples of the current output frame
+    frame: Vec<f32>,
+    frame_pos: usize,
+}
+
+impl<S> Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S) -> Self {
+        Self::with_ceiling(inner, LIMITER_CEILING_DB)
+    }
+
+    /// Creates a limiter whose output never exceeds `ceiling_db`.
+    pub fn with_ceiling(inner: S, ceiling_db: f64) -> Self {
+        let sample_rate = inner.sample_rate().max(1);
+        let lookahead_frames = (sample_rate * LIMITER_LOOKAHEAD_MS / 1000).max(1) as usize;
+        Self {
+            inner,
+            ceiling: db_to_linear(ceiling_db) as f32,
+            engaged: None,
+            active: true,
+            lookahead_frames,
+            // the gain reaches the required reduction within the look-ahead window
+            attack: 1.0 - (-5.0 / lookahead_frames as f32).exp(),
+            release: 1.0 - (-1000.0 / (LIMITER_RELEASE_MS * sample_rate as f32)).exp(),
+            delay: VecDeque::new(),
+            required: VecDeque::new(),
+            frames_read: 0,
+            frames_played: 0,
+            gain: 1.0,
+            frame: vec![],
+            frame_pos: 0,
+        }
+    }
+
+    /// Creates a limiter which is only engaged while `engaged` returns `true`, which is checked
+    /// once per look-ahead window. Otherwise the samples pass unchanged, delayed by the look-ahead
+    /// so that engaging the limiter does not skip any samples.
+    pub fn engaged_when(inner: S, engaged: impl Fn() -> bool + Send + 'static) -> Self {
+        let mut limiter = Self::new(inner);
+        limiter.engaged = Some(Box::new(engaged));
+        limiter
+    }
+
+    /// Changes the ceiling, an infinite ceiling turns the limiter off.
+    pub fn set_ceiling(&mut self, ceiling_db: f64) {
+        self.ceiling = db_to_linear(ceiling_db) as f32;
+    }
+
+    /// Returns the level which is currently held.
+    fn current_ceiling(&self) -> f32 {
+        match self.active {
+            true => self.ceiling,
+            false => f32::INFINITY,
+        }
+    }
+
+    /// Reads frames from the inner source until the look-ahead window is full.
+    fn fill(&mut self, channels: usize) {
+        while self.delay.len() < (self.lookahead_frames + 1) * channels {
+            let mut peak = 0.0f32;
+            let mut read = 0;
+            while read < channels {
+                let Some(sample) = self.inner.next() else {
+                    break;
+                };
+                peak = peak.max(sample.abs());
+                self.delay.push_back(sample);
+                read += 1;
+            }
+            if read == 0 {
+                return;
+            }
+            // an incomplete last frame is padded with silence
+            self.delay.extend((read..channels).map(|_| 0.0));
+
+            let ceiling = self.current_ceiling();
+            let required = match peak > ceiling {
+                true => ceiling / peak,
+                false => 1.0,
+            };
+            while self.required.back().is_some_and(|(_, g)| *g >= required) {
+                self.required.pop_back();
+            }
+            self.required.push_back((self.frames_read, required));
+            self.frames_read += 1;
+        }
+    }
+
+    /// Applies the gain to the next delayed frame.
+    /// Returns `false` if the inner source has ended and all frames have been played.
+    fn next_frame(&mut self) -> bool {
+        let channels = self.inner.channels().max(1) as usize;
+        if let Some(engaged) = &self.engaged {
+            if self
+                .frames_played
+                .is_multiple_of(self.lookahead_frames as u64)
+            {
+                self.active = engaged();
+            }
+        }
+        self.fill(channels);
+        if self.delay.is_empty() {
+            return false;
+        }
+
+        while self
+            .required
+            .front()
+            .is_some_and(|(idx, _)| *idx < self.frames_played)
+        {
+            self.required.pop_front();
+        }
+        let target = self.required.front().map_or(1.0, |(_, g)| *g);
+        let coeff = match target < self.gain {
+            true => self.attack,
+            false => self.release,
+        };
+        self.gain += (target - self.gain) * coeff;
+
+        let ceiling = self.current_ceiling();
+        self.frame.clear();
+        for _ in 0..channels {
+            let sample = self.delay.pop_front().unwrap_or_default() * self.gain;
+            // the smoothed gain may lag behind a sudden peak, which must not clip either
+            self.frame.push(sample.clamp(-ceiling, ceiling));
+        }
+        self.frame_pos = 0;
+        self.frames_played += 1;
+        true
+    }
+}
+
+impl<S> Iterator for Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.frame_pos == self.frame.len() && !self.next_frame() {
+            return None;
+        }
+        let sample = self.frame[self.frame_pos];
+        self.frame_pos += 1;
+        Some(sample)
+    }
+}
+
+impl<S> Source for Limiter<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        // the delayed samples were already taken from the inner source
+        let buffered = self.delay.len() + self.frame.len() - self.frame_pos;
+        self.inner.current_frame_len().map(|len| len + buffered)
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    /// The look-ahead in frames at the sample rate of the tests.
+    const LOOKAHEAD_FRAMES: usize = (SAMPLE_RATE * LIMITER_LOOKAHEAD_MS / 1000) as usize;
+
+    fn source(samples: Vec<f32>) -> SamplesBuffer<f32> {
+        SamplesBuffer::new(1, SAMPLE_RATE, samples)
+    }
+
+    /// Returns a sine wave with the given `amplitude`, one period lasts 48 frames.
+    fn sine(amplitude: f32, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| amplitude * (i as f32 * std::f32::consts::TAU / 48.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn holds_the_ceiling() {
+        let ceiling = db_to_linear(LIMITER_CEILING_DB) as f32;
+        let output: Vec<f32> = Limiter::new(source(sine(2.0, SAMPLE_RATE as usize))).collect();
+
+        assert_eq!(output.len(), SAMPLE_RATE as usize);
+        let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak <= ceiling, "peak {peak} exceeds the ceiling {ceiling}");
+        // the gain is reduced rather than the peaks being clipped, so the level stays close
+        assert!(peak > ceiling * 0.9, "peak {peak} is reduced too far");
+    }
+
+    #[test]
+    fn passes_samples_below_the_ceiling_unchanged() {
+        let input = sine(0.5, 4800);
+        let output: Vec<f32> = Limiter::new(source(input.clone())).collect();
+        assert_eq!(output, input);
+
+        let loud = sine(2.0, 4800);
+        let output: Vec<f32> = Limiter::engaged_when(source(loud.clone()), || false).collect();
+        assert_eq!(output, loud);
+    }
+
+    #[test]
+    fn reduces_the_gain_ahead_of_a_peak() {
+        let peak_frame = 1000;
+        let mut input = vec![0.5; 2000];
+        input[peak_frame] = 2.0;
+        let output: Vec<f32> = Limiter::new(source(input)).collect();
+
+        // the frames before the look-ahead window are unaffected
+        assert!(output[..peak_frame - LOOKAHEAD_FRAMES]
+            .iter()
+            .all(|s| *s == 0.5));
+        // the gain is already reduced when the peak is reached instead of clipping the peak
+        assert!(output[peak_frame - 1] < 0.5);
+        let ceiling = db_to_linear(LIMITER_CEILING_DB) as f32;
+        assert!(output[peak_frame] <= ceiling);
+        assert!(output[peak_frame] > ceiling * 0.9);
+    }
+}
@@ -0,0 +1,300 @@
+use rodio::Source;
+use std::{collections::VecDeque, f64::consts::PI, time::Duration};
+
+use super::{biquad::Biquad, db_to_linear};
+use crate::config::NormalizationConfig;
+
+/// The length of a gating block as specified by EBU R128 / ITU-R BS.1770.
+const BLOCKS_PER_GATING_BLOCK: usize = 4;
+/// Gating blocks overlap by 75%, so a new block is completed every 100 milliseconds.
+const HOPS_PER_SEC: u32 = 10;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// The time constant of the gain smoothing.
+const GAIN_SMOOTHING_SECS: f64 = 2.0;
+
+/// Measures the integrated loudness of the wrapped source on the fly
+/// and smoothly adjusts its gain so that it matches the target loudness.
+/// The integrated loudness is gated according to EBU R128 over a sliding window.
+pub struct LoudnessNormalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    inner: S,
+    config: NormalizationConfig,
+    /// The manual gain offset of the station in dB
+    offset_db: f64,
+
+    sample_rate: u32,
+    channels: u16,
+    /// The K-weighting filters (pre-filter and RLB filter) of each channel
+    filters: Vec<(Biquad, Biquad)>,
+    channel: usize,
+
+    hop_len: u32,
+    hop_frames: u32,
+    hop_energy: Vec<f64>,
+    hops: VecDeque<f64>,
+    blocks: VecDeque<f64>,
+
+    gain: f64,
+    target_gain: f64,
+    smoothing: f64,
+}
+
+impl<S> LoudnessNormalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S, config: NormalizationConfig, offset_db: f32) -> Self {
+        let offset_gain = db_to_linear(offset_db as f64);
+        let mut normalizer = Self {
+            inner,
+            config,
+            offset_db: offset_db as f64,
+            sample_rate: 0,
+            channels: 0,
+            filters: vec![],
+            channel: 0,
+            hop_len: 0,
+            hop_frames: 0,
+            hop_energy: vec![],
+            hops: VecDeque::with_capacity(BLOCKS_PER_GATING_BLOCK),
+            blocks: VecDeque::new(),
+            gain: offset_gain,
+            target_gain: offset_gain,
+            smoothing: 0.0,
+        };
+        normalizer.reset_format();
+        normalizer
+    }
+
+    /// (Re)initializes the filters for the current sample rate and channel count of the source.
+    fn reset_format(&mut self) {
+        self.sample_rate = self.inner.sample_rate().max(1);
+        self.channels = self.inner.channels().max(1);
+
+        self.filters = vec![k_weighting(self.sample_rate); self.channels as usize];
+        self.channel = 0;
+        self.hop_len = (self.sample_rate / HOPS_PER_SEC).max(1);
+        self.hop_frames = 0;
+        self.hop_energy = vec![0.0; self.channels as usize];
+        self.hops.clear();
+        self.smoothing = 1.0 - (-1.0 / (GAIN_SMOOTHING_SECS * self.sample_rate as f64)).exp();
+    }
+
+    fn finish_hop(&mut self) {
+        let energy = self
+            .hop_energy
+            .iter()
+            .map(|sum| sum / self.hop_len as f64)
+            .sum::<f64>();
+        self.hop_energy.iter_mut().for_each(|sum| *sum = 0.0);
+        self.hop_frames = 0;
+
+        if self.hops.len() == BLOCKS_PER_GATING_BLOCK {
+            self.hops.pop_front();
+        }
+        self.hops.push_back(energy);
+        if self.hops.len() < BLOCKS_PER_GATING_BLOCK {
+            return;
+        }
+
+        let max_blocks = (self.config.window_secs * HOPS_PER_SEC as u64) as usize;
+        if self.blocks.len() >= max_blocks {
+            self.blocks.pop_front();
+        }
+        self.blocks
+            .push_back(self.hops.iter().sum::<f64>() / BLOCKS_PER_GATING_BLOCK as f64);
+
+        // during silence, the previous gain is retained
+        if let Some(integrated) = self.integrated_loudness() {
+            let max_gain = self.config.max_gain_db as f64;
+            let gain_db = (self.config.target_lufs as f64 - integrated).clamp(-max_gain, max_gain);
+            self.target_gain = db_to_linear(gain_db + self.offset_db);
+        }
+    }
+
+    /// Calculates the gated integrated loudness of the blocks in the window.
+    fn integrated_loudness(&self) -> Option<f64> {
+        let above_absolute: Vec<f64> = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|&energy| loudness(energy) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if above_absolute.is_empty() {
+            return None;
+        }
+
+        let relative_gate = loudness(mean(above_absolute.iter().copied())) + RELATIVE_GATE_LU;
+        let gated = above_absolute
+            .into_iter()
+            .filter(|&energy| loudness(energy) > relative_gate);
+        Some(loudness(mean(gated)))
+    }
+}
+
+impl<S> Iterator for LoudnessNormalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0
+            && (self.inner.sample_rate() != self.sample_rate
+                || self.inner.channels() != self.channels)
+        {
+            self.reset_format();
+        }
+
+        let sample = self.inner.next()?;
+
+        let (pre, rlb) = &mut self.filters[self.channel];
+        let weighted = rlb.process(pre.process(sample as f64));
+        self.hop_energy[self.channel] += weighted * weighted;
+
+        self.channel += 1;
+        if self.channel == self.channels as usize {
+            self.channel = 0;
+            // the gain is only changed between frames so that all channels are treated equally
+            self.gain += (self.target_gain - self.gain) * self.smoothing;
+
+            self.hop_frames += 1;
+            if self.hop_frames == self.hop_len {
+                self.finish_hop();
+            }
+        }
+
+        Some((sample as f64 * self.gain) as f32)
+    }
+}
+
+impl<S> Source for LoudnessNormalizer<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    sum / count.max(1) as f64
+}
+
+/// Creates the two-stage K-weighting filter of ITU-R BS.1770 for the given sample rate.
+/// The coefficients are derived the same way as in `libebur128`.
+fn k_weighting(sample_rate: u32) -> (Biquad, Biquad) {
+    let rate = sample_rate as f64;
+
+    // stage 1: high shelf which models the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let g = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(g / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let pre = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // stage 2: high pass (revised low-frequency B-curve)
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let rlb = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    (pre, rlb)
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    /// Normalizes a mono 997 Hz tone with the given `amplitude` for `secs` seconds.
+    /// Returns the gain in dB which is applied at the end.
+    fn normalize(amplitude: f32, config: NormalizationConfig, secs: usize) -> f64 {
+        let tone: Vec<f32> = (0..SAMPLE_RATE as usize * secs)
+            .map(|i| amplitude * (i as f32 * 997.0 * 2.0 * PI as f32 / SAMPLE_RATE as f32).sin())
+            .collect();
+        let output: Vec<f32> =
+            LoudnessNormalizer::new(SamplesBuffer::new(1, SAMPLE_RATE, tone), config, 0.0)
+                .collect();
+
+        let last_second = &output[output.len() - SAMPLE_RATE as usize..];
+        let peak = last_second.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        20.0 * (peak as f64 / amplitude as f64).log10()
+    }
+
+    fn config(max_gain_db: f32) -> NormalizationConfig {
+        NormalizationConfig {
+            target_lufs: -23.0,
+            max_gain_db,
+            window_secs: 3,
+        }
+    }
+
+    #[test]
+    fn converges_to_the_target_loudness() {
+        // a full scale sine wave at 997 Hz measures -3.01 LUFS, so this tone measures -9.03 LUFS
+        let gain_db = normalize(0.5, config(20.0), 15);
+        assert!(
+            (gain_db - -13.97).abs() < 0.5,
+            "gain of {gain_db:.2} dB misses the target"
+        );
+    }
+
+    #[test]
+    fn clamps_the_gain() {
+        // the tone measures -43 LUFS and would have to be raised by 20 dB
+        let gain_db = normalize(0.01, config(6.0), 15);
+        assert!(
+            (gain_db - 6.0).abs() < 0.1,
+            "gain of {gain_db:.2} dB exceeds the maximum"
+        );
+
+        let gain_db = normalize(0.9, config(6.0), 15);
+        assert!(
+            (gain_db - -6.0).abs() < 0.1,
+            "attenuation of {gain_db:.2} dB exceeds the maximum"
+        );
+    }
+}
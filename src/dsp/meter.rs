@@ -0,0 +1,233 @@
+use rodio::Source;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::Serialize;
+use std::{collections::VecDeque, f32::consts::PI, sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+/// How often the levels are published while someone is listening.
+const METER_UPDATE_INTERVAL_MS: u32 = 100;
+const FFT_SIZE: usize = 2048;
+const SPECTRUM_BANDS: usize = 16;
+const SPECTRUM_MIN_FREQUENCY: f32 = 40.0;
+const SPECTRUM_MAX_FREQUENCY: f32 = 16_000.0;
+/// The lowest level which is reported, silence is reported at this level as well.
+const METER_FLOOR_DB: f32 = -90.0;
+
+/// The levels of the played audio during the last update interval (in dBFS).
+#[derive(Serialize, Clone, Default)]
+pub struct Levels {
+    /// The peak level of each channel
+    pub peak: Vec<f32>,
+    /// The RMS level of each channel
+    pub rms: Vec<f32>,
+    /// The levels of logarithmically spaced frequency bands from low to high
+    pub spectrum: Vec<f32>,
+}
+
+/// Distributes the levels of the played audio to all listeners.
+pub struct LevelMeter {
+    tx: watch::Sender<Levels>,
+}
+
+impl LevelMeter {
+    pub fn new() -> Self {
+        Self {
+            tx: watch::channel(Levels::default()).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Levels> {
+        self.tx.subscribe()
+    }
+
+    /// Levels are only measured while there are listeners.
+    fn is_listened_to(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+}
+
+/// Measures the levels of the wrapped source and publishes them using a [`LevelMeter`].
+/// The samples are passed through unchanged.
+pub struct MeterTap<S>
+where
+    S: Source<Item = f32>,
+{
+    inner: S,
+    meter: Arc<LevelMeter>,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+
+    /// Whether the current interval is measured
+    active: bool,
+    /// The number of samples left in the current interval
+    remaining: usize,
+    channel: usize,
+    peak: Vec<f32>,
+    square_sum: Vec<f32>,
+    frames: usize,
+    /// The most recent mono samples which are used for the spectrum
+    history: VecDeque<f32>,
+    mono: f32,
+}
+
+impl<S> MeterTap<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S, meter: Arc<LevelMeter>) -> Self {
+        // a Hann window reduces the spectral leakage between the bands
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        Self {
+            inner,
+            meter,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            active: false,
+            remaining: 0,
+            channel: 0,
+            peak: vec![],
+            square_sum: vec![],
+            frames: 0,
+            history: VecDeque::with_capacity(FFT_SIZE),
+            mono: 0.0,
+        }
+    }
+
+    fn start_interval(&mut self) {
+        let channels = self.inner.channels().max(1) as usize;
+        self.active = self.meter.is_listened_to();
+        self.remaining =
+            (self.inner.sample_rate() as usize * METER_UPDATE_INTERVAL_MS as usize / 1000).max(1)
+                * channels;
+        self.channel = 0;
+        self.peak = vec![0.0; channels];
+        self.square_sum = vec![0.0; channels];
+        self.frames = 0;
+        self.mono = 0.0;
+        if !self.active {
+            self.history.clear();
+        }
+    }
+
+    fn measure(&mut self, sample: f32) {
+        let channels = self.peak.len();
+        self.peak[self.channel] = self.peak[self.channel].max(sample.abs());
+        self.square_sum[self.channel] += sample * sample;
+        self.mono += sample / channels as f32;
+
+        self.channel += 1;
+        if self.channel == channels {
+            self.channel = 0;
+            self.frames += 1;
+            if self.history.len() == FFT_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(self.mono);
+            self.mono = 0.0;
+        }
+    }
+
+    fn publish(&self) {
+        let frames = self.frames.max(1) as f32;
+        let levels = Levels {
+            peak: self.peak.iter().map(|p| to_db(*p)).collect(),
+            rms: self
+                .square_sum
+                .iter()
+                .map(|s| to_db((s / frames).sqrt()))
+                .collect(),
+            spectrum: self.spectrum(),
+        };
+        let _ = self.meter.tx.send(levels);
+    }
+
+    fn spectrum(&self) -> Vec<f32> {
+        if self.history.len() < FFT_SIZE {
+            return vec![METER_FLOOR_DB; SPECTRUM_BANDS];
+        }
+
+        let mut buffer: Vec<Complex<f32>> = self
+            .history
+            .iter()
+            .zip(&self.window)
+            .map(|(s, w)| Complex::new(s * w, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+
+        // the magnitudes are scaled so that a full scale sine is reported at 0 dBFS
+        // (the Hann window halves the amplitude)
+        let scale = 4.0 / FFT_SIZE as f32;
+        let bin_width = self.inner.sample_rate() as f32 / FFT_SIZE as f32;
+        let ratio = SPECTRUM_MAX_FREQUENCY / SPECTRUM_MIN_FREQUENCY;
+
+        (0..SPECTRUM_BANDS)
+            .map(|band| {
+                let edge = |band: usize| {
+                    SPECTRUM_MIN_FREQUENCY * ratio.powf(band as f32 / SPECTRUM_BANDS as f32)
+                };
+                let low = (edge(band) / bin_width) as usize;
+                let high = ((edge(band + 1) / bin_width) as usize).clamp(low + 1, FFT_SIZE / 2);
+                let magnitude = buffer[low.min(high - 1)..high]
+                    .iter()
+                    .fold(0.0f32, |max, bin| max.max(bin.norm()));
+                to_db(magnitude * scale)
+            })
+            .collect()
+    }
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    (20.0 * amplitude.log10()).max(METER_FLOOR_DB)
+}
+
+impl<S> Iterator for MeterTap<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+
+        if self.remaining == 0 {
+            self.start_interval();
+        }
+        if self.active {
+            self.measure(sample);
+        }
+        self.remaining -= 1;
+        if self.remaining == 0 && self.active {
+            self.publish();
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for MeterTap<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
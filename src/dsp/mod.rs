@@ -0,0 +1,87 @@
+use rodio::Source;
+use std::sync::Arc;
+
+use crate::config::{NormalizationConfig, Station};
+
+mod biquad;
+pub mod channels;
+pub mod compressor;
+pub mod equalizer;
+pub mod gain;
+pub mod limiter;
+mod loudness;
+pub mod meter;
+pub mod split;
+
+use compressor::{Compressor, CompressorControl, CompressorPreset};
+use equalizer::{Equalizer, EqualizerControl, EqualizerSettings};
+use loudness::LoudnessNormalizer;
+
+pub type DspSource = Box<dyn Source<Item = f32> + Send>;
+
+/// The processing stages which are applied between the decoder and the sink.
+#[derive(Clone)]
+pub struct DspChain {
+    normalization: Option<NormalizationConfig>,
+    equalizer: Arc<EqualizerControl>,
+    compressor: Arc<CompressorControl>,
+}
+
+impl DspChain {
+    pub fn new(
+        normalization: Option<NormalizationConfig>,
+        equalizer: EqualizerSettings,
+        compressor: CompressorPreset,
+    ) -> Self {
+        Self {
+            normalization,
+            equalizer: Arc::new(EqualizerControl::new(equalizer)),
+            compressor: Arc::new(CompressorControl::new(compressor)),
+        }
+    }
+
+    pub fn equalizer(&self) -> &EqualizerControl {
+        &self.equalizer
+    }
+
+    pub fn compressor(&self) -> &CompressorControl {
+        &self.compressor
+    }
+
+    /// Returns whether the stages may raise the level of a station with the given `gain_db`
+    /// above the level it was decoded at.
+    pub fn boosts(&self, gain_db: f32) -> bool {
+        let max_gain_db = match &self.normalization {
+            Some(config) => config.max_gain_db + gain_db,
+            None => gain_db,
+        };
+        max_gain_db > 0.0 || self.equalizer.boosts()
+    }
+
+    /// Wraps the decoded `source` of the `station` with all processing stages.
+    pub fn apply<S>(&self, source: S, station: &Station) -> DspSource
+    where
+        S: Source<Item = i16> + Send + 'static,
+    {
+        let source = source.convert_samples::<f32>();
+
+        // the loudness is measured before any other processing takes place
+        let source: DspSource = match &self.normalization {
+            Some(config) => Box::new(LoudnessNormalizer::new(
+                source,
+                config.clone(),
+                station.gain_db,
+            )),
+            None => Box::new(source.amplify(db_to_linear(station.gain_db as f64) as f32)),
+        };
+
+        let source = Equalizer::new(source, self.equalizer.clone());
+
+        // the limiter of the compressor has to be the last stage so that the ceiling holds
+        Box::new(Compressor::new(source, self.compressor.clone()))
+    }
+}
+
+pub fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
@@ -0,0 +1,236 @@
+use rodio::Source;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::DspSource;
+
+/// The number of frames which are read from the shared source at once.
+const SPLIT_CHUNK_FRAMES: usize = 512;
+/// How far a branch may fall behind the fastest branch before its oldest samples are dropped.
+/// Output devices are driven by independent clocks, so their playback slowly drifts apart.
+const SPLIT_MAX_LAG_MS: usize = 500;
+
+struct Shared {
+    inner: DspSource,
+    /// The samples which have been read from the inner source but not by the branch yet
+    pending: Vec<VecDeque<f32>>,
+    max_lag_samples: usize,
+}
+
+/// One of several sources which all play the samples of a single shared source.
+/// Whichever branch needs samples first reads them from the shared source,
+/// the other branches receive a copy.
+pub struct Branch {
+    shared: Arc<Mutex<Shared>>,
+    index: usize,
+    /// A follower never reads from the shared source, it only plays the copies it receives
+    follower: bool,
+    channels: u16,
+    sample_rate: u32,
+    total_duration: Option<Duration>,
+
+    /// The samples which have been handed to this branch
+    chunk: Vec<f32>,
+    chunk_pos: usize,
+}
+
+/// Splits the source into `count` branches.
+pub fn split(inner: DspSource, count: usize) -> Vec<Branch> {
+    let channels = inner.channels().max(1);
+    let sample_rate = inner.sample_rate();
+    let total_duration = inner.total_duration();
+    let shared = Arc::new(Mutex::new(Shared {
+        inner,
+        pending: vec![VecDeque::new(); count],
+        max_lag_samples: sample_rate as usize * channels as usize * SPLIT_MAX_LAG_MS / 1000,
+    }));
+
+    (0..count)
+        .map(|index| Branch {
+            shared: shared.clone(),
+            index,
+            follower: false,
+            channels,
+            sample_rate,
+            total_duration,
+            chunk: vec![],
+            chunk_pos: 0,
+        })
+        .collect()
+}
+
+impl Branch {
+    /// Creates a branch which plays the samples read by the other branches without driving the
+    /// shared source itself, so its consumer cannot affect their timing.
+    /// It plays silence while there are no samples and ends once all other branches are gone.
+    pub fn follower(&self) -> Branch {
+        let mut shared = self.shared.lock().unwrap();
+        shared.pending.push(VecDeque::new());
+        Branch {
+            shared: self.shared.clone(),
+            index: shared.pending.len() - 1,
+            follower: true,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            total_duration: None,
+            chunk: vec![],
+            chunk_pos: 0,
+        }
+    }
+
+    /// Replaces the chunk with the samples which are pending for this branch or,
+    /// if there are none, with new samples from the shared source.
+    fn refill(&mut self) {
+        let mut guard = self.shared.lock().unwrap();
+        let shared = &mut *guard;
+        let channels = self.channels as usize;
+        self.chunk.clear();
+        self.chunk_pos = 0;
+
+        let pending = &mut shared.pending[self.index];
+        if !pending.is_empty() {
+            let len = pending.len().min(SPLIT_CHUNK_FRAMES * channels);
+            self.chunk.extend(pending.drain(..len));
+            return;
+        }
+        if self.follower {
+            // whole frames of silence keep the channels in order
+            if Arc::strong_count(&self.shared) > 1 {
+                self.chunk.resize(channels, 0.0);
+            }
+            return;
+        }
+
+        self.chunk
+            .extend(shared.inner.by_ref().take(SPLIT_CHUNK_FRAMES * channels));
+
+        let max_lag_samples = shared.max_lag_samples;
+        for (index, pending) in shared.pending.iter_mut().enumerate() {
+            if index == self.index {
+                continue;
+            }
+            pending.extend(&self.chunk);
+            if pending.len() > max_lag_samples {
+                // only whole frames are dropped so that the channels stay in order
+                let excess = (pending.len() - max_lag_samples).div_ceil(channels) * channels;
+                pending.drain(..excess.min(pending.len()));
+            }
+        }
+    }
+}
+
+impl Iterator for Branch {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.chunk_pos >= self.chunk.len() {
+            self.refill();
+        }
+        let sample = *self.chunk.get(self.chunk_pos)?;
+        self.chunk_pos += 1;
+        Some(sample)
+    }
+}
+
+impl Source for Branch {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    /// At 4 kHz stereo, a branch may fall 4000 samples behind.
+    const SAMPLE_RATE: u32 = 4000;
+    const MAX_LAG_SAMPLES: usize = 4000;
+
+    /// Returns the samples of a stereo source which counts up, and its branches.
+    fn branches(frames: usize, count: usize) -> (Vec<f32>, Vec<Branch>) {
+        let samples: Vec<f32> = (0..frames * 2).map(|i| i as f32).collect();
+        let source = SamplesBuffer::new(2, SAMPLE_RATE, samples.clone());
+        (samples, split(Box::new(source), count))
+    }
+
+    #[test]
+    fn branches_receive_identical_samples() {
+        let (samples, mut branches) = branches(8000, 2);
+
+        // the branches take turns in reading from the shared source, at different offsets
+        let mut a: Vec<f32> = branches[0].by_ref().take(1500).collect();
+        let mut b = vec![];
+        loop {
+            let read_a = a.len();
+            a.extend(branches[0].by_ref().take(300));
+            let read_b = b.len();
+            b.extend(branches[1].by_ref().take(300));
+            if a.len() == read_a && b.len() == read_b {
+                break;
+            }
+        }
+        assert_eq!(a, samples);
+        assert_eq!(b, samples);
+    }
+
+    #[test]
+    fn lagging_branch_does_not_block_the_main_output() {
+        let (samples, mut branches) = branches(8000, 2);
+        let main: Vec<f32> = branches[0].by_ref().collect();
+        assert_eq!(main, samples);
+
+        // the lagging branch skips to the newest samples, starting at a whole frame
+        let lagging: Vec<f32> = branches[1].by_ref().collect();
+        assert!(lagging.len() <= MAX_LAG_SAMPLES);
+        assert_eq!(lagging, samples[samples.len() - lagging.len()..]);
+        assert_eq!(lagging[0] as usize % 2, 0);
+    }
+
+    #[test]
+    fn dropped_branch_does_not_block_the_main_output() {
+        let (samples, mut branches) = branches(8000, 2);
+        let follower = branches[0].follower();
+        drop(branches.pop());
+        drop(follower);
+
+        let main: Vec<f32> = branches[0].by_ref().collect();
+        assert_eq!(main, samples);
+    }
+
+    #[test]
+    fn follower_plays_silence_until_samples_are_read() {
+        let (samples, mut branches) = branches(100, 1);
+        let mut follower = branches[0].follower();
+        assert_eq!(follower.by_ref().take(4).collect::<Vec<_>>(), [0.0; 4]);
+
+        let main: Vec<f32> = branches[0].by_ref().collect();
+        assert_eq!(main, samples);
+        drop(branches);
+
+        // the follower ends once the samples it received have been played
+        let followed: Vec<f32> = follower.collect();
+        assert_eq!(followed, samples);
+    }
+}
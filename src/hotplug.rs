@@ -0,0 +1,227 @@
+use std::collections::{HashMap, HashSet};
+
+use tokio::task;
+
+use crate::{
+    audio::{self, OutputDevice},
+    output::VirtualHost,
+    zapping,
+    zone::Zone,
+    State,
+};
+
+/// A change of the output devices of the host.
+pub(crate) enum DeviceEvent {
+    Added(String),
+    Removed(String),
+}
+
+/// Detects output devices which are plugged in or removed.
+#[derive(Default)]
+pub(crate) struct DeviceWatcher {
+    /// The devices of each watched host which were present during the previous poll
+    known: HashMap<Option<String>, HashSet<String>>,
+}
+
+impl DeviceWatcher {
+    /// Stops watching the hosts which are not among the `hosts`, so that they start over
+    /// once they are watched again.
+    fn retain_hosts(&mut self, hosts: &[Option<String>]) {
+        self.known.retain(|host, _| hosts.contains(host));
+    }
+
+    /// Enumerates the output devices of `host` and returns the changes since the previous poll.
+    /// The first poll of a host only records the devices which are present.
+    pub(crate) async fn poll(
+        &mut self,
+        host: Option<String>,
+    ) -> Result<(HashSet<String>, Vec<DeviceEvent>), audio::Error> {
+        // enumerating devices may take a while, depending on the host
+        let list_host = host.clone();
+        let devices: HashSet<String> =
+            match task::spawn_blocking(move || audio::list_device_names(list_host.as_deref()))
+                .await
+                .expect("listing devices does not panic")
+            {
+                Ok(names) => names.into_iter().collect(),
+                // the devices of a host which went away are added again once it returns
+                Err(err @ audio::Error::HostUnavailable(_)) => {
+                    self.known.insert(host, HashSet::new());
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            };
+
+        let events = match self.known.get(&host) {
+            Some(known) => devices
+                .difference(known)
+                .map(|name| DeviceEvent::Added(name.clone()))
+                .chain(
+                    known
+                        .difference(&devices)
+                        .map(|name| DeviceEvent::Removed(name.clone())),
+                )
+                .collect(),
+            None => vec![],
+        };
+        self.known.insert(host, devices.clone());
+        Ok((devices, events))
+    }
+}
+
+/// Polls the output devices of the zone. If the selected device disappears, playback moves to the
+/// default device unless another output already uses it. Once the selected device reappears,
+/// playback returns to it. Mirror outputs which reappear are opened again.
+pub(crate) async fn watch(data: &State, zone: &Zone, watcher: &mut DeviceWatcher) {
+    let (preferred, mirrors) = {
+        let settings = zone.settings.lock().await;
+        let mirrors: Vec<OutputDevice> = settings
+            .mirrors
+            .iter()
+            .filter(|m| m.enabled && !VirtualHost::is_virtual(&m.device))
+            .map(|m| m.device.clone())
+            .collect();
+        (settings.output_device.clone(), mirrors)
+    };
+    // virtual outputs never disappear and playback on them is never moved elsewhere
+    let watch_preferred = !VirtualHost::is_virtual(&preferred)
+        && !VirtualHost::is_virtual(zone.player.lock().await.device());
+
+    let mut hosts: Vec<Option<String>> = mirrors.iter().map(|m| m.host.clone()).collect();
+    if watch_preferred {
+        hosts.push(preferred.host.clone());
+    }
+    hosts.sort();
+    hosts.dedup();
+    watcher.retain_hosts(&hosts);
+
+    let mut devices = HashMap::new();
+    let mut added = HashSet::new();
+    for host in hosts {
+        match watcher.poll(host.clone()).await {
+            Ok((names, events)) => {
+                for event in events {
+                    match event {
+                        DeviceEvent::Added(name) => {
+                            info!("Output device `{name}` was added");
+                            added.insert((host.clone(), name));
+                        }
+                        DeviceEvent::Removed(name) => info!("Output device `{name}` was removed"),
+                    }
+                }
+                devices.insert(host, names);
+            }
+            // a host which went away (e.g. a stopped JACK server) has no devices
+            Err(err @ audio::Error::HostUnavailable(_)) => {
+                warn!("Could not list output devices: {err}");
+                devices.insert(host, HashSet::new());
+            }
+            Err(err) => warn!("Could not list output devices: {err}"),
+        }
+    }
+
+    // mirror outputs which could not be opened while they were missing are opened once they return
+    let reappeared: Vec<&OutputDevice> = mirrors
+        .iter()
+        .filter(|m| added.contains(&(m.host.clone(), m.name.clone())))
+        .collect();
+
+    // devices of migrated settings may not have a name, they are only known by their index
+    let target = match devices.get(&preferred.host) {
+        Some(devices) if watch_preferred && !preferred.name.is_empty() => {
+            preferred_target(data, zone, &preferred, &mirrors, devices).await
+        }
+        _ => None,
+    };
+
+    let mut player = zone.player.lock().await;
+    let result = match target {
+        // the mirror outputs are reopened along with the output device
+        Some(target) => player.change_device(target).await,
+        None if !reappeared.is_empty() => {
+            for mirror in reappeared {
+                info!("Mirror output `{}` reappeared, reopening it", mirror.name);
+            }
+            player.reopen_outputs().await
+        }
+        None => return,
+    };
+    match result {
+        Ok(_) => zapping::update_preconnections(&data.config, &mut player),
+        Err(err) => error!("Could not restart player on another output device: {err}"),
+    }
+}
+
+/// Returns the device playback of the zone has to move to, if any.
+async fn preferred_target(
+    data: &State,
+    zone: &Zone,
+    preferred: &OutputDevice,
+    mirrors: &[OutputDevice],
+    devices: &HashSet<String>,
+) -> Option<OutputDevice> {
+    let current = zone.player.lock().await.device().clone();
+    match (current == *preferred, devices.contains(&preferred.name)) {
+        (true, false) => match fallback_device(preferred.host.as_deref()) {
+            Ok(default) if default.host == preferred.host && default.name == preferred.name => None,
+            Ok(default) if is_in_use(data, zone, mirrors, &default).await => {
+                warn!(
+                    "Output device `{}` disappeared and its fallback `{}` is already in use",
+                    preferred.name, default.name
+                );
+                None
+            }
+            Ok(default) => {
+                warn!(
+                    "Output device `{}` disappeared, falling back to `{}`",
+                    preferred.name, default.name
+                );
+                Some(default)
+            }
+            Err(err) => {
+                warn!(
+                    "Output device `{}` disappeared and there is no fallback: {err}",
+                    preferred.name
+                );
+                None
+            }
+        },
+        (false, true) => {
+            info!(
+                "Output device `{}` reappeared, returning to it",
+                preferred.name
+            );
+            Some(preferred.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the `device` is a mirror output of the zone or is used by another zone.
+async fn is_in_use(
+    data: &State,
+    zone: &Zone,
+    mirrors: &[OutputDevice],
+    device: &OutputDevice,
+) -> bool {
+    if mirrors
+        .iter()
+        .any(|m| m.host == device.host && m.name == device.name)
+    {
+        return true;
+    }
+    for other in data.zones.iter().filter(|z| z.name != zone.name) {
+        if other.settings.lock().await.uses_device(device) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Returns the default device of `host` or, if the host has none, of the default host.
+fn fallback_device(host: Option<&str>) -> Result<audio::OutputDevice, audio::Error> {
+    audio::default_device(host).or_else(|err| match host {
+        Some(_) => audio::default_device(None),
+        None => Err(err),
+    })
+}
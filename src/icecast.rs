@@ -0,0 +1,346 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    config::IcecastConfig,
+    listen::{ListenBus, OGG_SERIAL},
+};
+
+/// How long connecting to the server and sending data may take before the connection is dropped.
+const ICECAST_TIMEOUT_SECS: u64 = 10;
+/// The delay before the first reconnect, it doubles with every failed attempt.
+const RECONNECT_MIN_DELAY_SECS: u64 = 1;
+const RECONNECT_MAX_DELAY_SECS: u64 = 60;
+/// A connection which lasted this long resets the reconnect delay.
+const RECONNECT_RESET_SECS: u64 = 60;
+/// The largest response header which is accepted from the server.
+const MAX_RESPONSE_BYTES: usize = 4096;
+
+/// Sends the listen stream of a zone to an Icecast server as a source.
+/// The connection is reestablished whenever it fails, until the listen stream ends.
+pub(crate) fn spawn(config: IcecastConfig, bus: Arc<ListenBus>) {
+    thread::spawn(move || {
+        let mut delay = Duration::from_secs(RECONNECT_MIN_DELAY_SECS);
+        loop {
+            let start = Instant::now();
+            match send(&config, &bus) {
+                Ok(()) => {
+                    debug!("Listen stream has ended, stopping Icecast source");
+                    return;
+                }
+                Err(err) => warn!(
+                    "Icecast source `{}` is not connected: {err:#}, reconnecting in {}s",
+                    config.mount,
+                    delay.as_secs()
+                ),
+            }
+            if start.elapsed() >= Duration::from_secs(RECONNECT_RESET_SECS) {
+                delay = Duration::from_secs(RECONNECT_MIN_DELAY_SECS);
+            }
+            thread::sleep(delay);
+            delay = (delay * 2).min(Duration::from_secs(RECONNECT_MAX_DELAY_SECS));
+        }
+    });
+}
+
+/// Connects to the server and streams until the connection fails.
+/// Returns `Ok` once the listen stream has ended.
+fn send(config: &IcecastConfig, bus: &ListenBus) -> Result<()> {
+    let mut stream = connect(config)?;
+    info!(
+        "Icecast source connected to `{}:{}{}`",
+        config.host, config.port, config.mount
+    );
+
+    let mut packets = bus.packets();
+    let mut muxer = bus.muxer();
+    let mut title = bus.title();
+    stream
+        .write_all(&muxer.start(OGG_SERIAL, stream_title(&title)))
+        .context("could not send stream headers")?;
+
+    loop {
+        let packet = match packets.blocking_recv() {
+            Ok(packet) => packet,
+            // the server is slower than real time, the stream continues with the latest packets
+            Err(RecvError::Lagged(skipped)) => {
+                debug!("Icecast source fell behind, skipping {skipped} packets");
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+
+        let current = bus.title();
+        let data = match current == title {
+            true => muxer.push(&packet),
+            // Ogg carries the metadata in the stream, so a new stream with the new title is chained
+            false => {
+                debug!("Updating Icecast metadata to `{current}`");
+                title = current;
+                let mut data = muxer.finish(&packet).to_vec();
+                data.extend(muxer.start(muxer.serial().wrapping_add(1), stream_title(&title)));
+                Some(data.into())
+            }
+        };
+        if let Some(data) = data {
+            stream.write_all(&data).context("could not send stream")?;
+        }
+    }
+}
+
+/// Nothing has been played yet if the title is empty.
+fn stream_title(title: &str) -> Option<&str> {
+    (!title.is_empty()).then_some(title)
+}
+
+/// Opens a connection to the server and authenticates as a source of the mount.
+fn connect(config: &IcecastConfig) -> Result<TcpStream> {
+    let timeout = Duration::from_secs(ICECAST_TIMEOUT_SECS);
+    let addr = (config.host.as_str(), config.port)
+        .to_socket_addrs()
+        .with_context(|| format!("could not resolve `{}`", config.host))?
+        .next()
+        .with_context(|| format!("no address found for `{}`", config.host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).context("could not connect")?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // the source protocol of Icecast 2.4 and newer is a regular HTTP PUT request
+    let credentials = BASE64.encode(format!("{}:{}", config.username, config.password));
+    let mut request = format!(
+        "PUT {} HTTP/1.1\r\n\
+         Host: {}:{}\r\n\
+         Authorization: Basic {credentials}\r\n\
+         User-Agent: radio/{}\r\n\
+         Content-Type: audio/ogg\r\n\
+         Ice-Public: {}\r\n\
+         Expect: 100-continue\r\n",
+        config.mount,
+        config.host,
+        config.port,
+        env!("CARGO_PKG_VERSION"),
+        config.public as u8,
+    );
+    for (header, value) in [
+        ("Ice-Name", &config.name),
+        ("Ice-Description", &config.description),
+        ("Ice-Genre", &config.genre),
+    ] {
+        if let Some(value) = value {
+            request.push_str(&format!("{header}: {value}\r\n"));
+        }
+    }
+    request.push_str("\r\n");
+    stream
+        .write_all(request.as_bytes())
+        .context("could not send request")?;
+
+    let status = read_status(&mut stream)?;
+    match status {
+        100 | 200 => Ok(stream),
+        401 => bail!("the server rejected the credentials"),
+        403 => bail!("the mount is in use or not allowed"),
+        status => bail!("the server responded with status {status}"),
+    }
+}
+
+/// Reads the response header of the server and returns its status code.
+fn read_status(stream: &mut TcpStream) -> Result<u16> {
+    let mut response = vec![];
+    let mut byte = [0];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() == MAX_RESPONSE_BYTES {
+            bail!("the response of the server is too long")
+        }
+        // reading byte by byte never consumes more than the header
+        match stream.read(&mut byte).context("could not read response")? {
+            0 => bail!("the server closed the connection"),
+            _ => response.push(byte[0]),
+        }
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .with_context(|| {
+            format!(
+                "invalid response: `{}`",
+                response.lines().next().unwrap_or_default()
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, ErrorKind, Seek, SeekFrom},
+        net::TcpListener,
+    };
+
+    use ogg::{reading::PacketReader, Packet};
+
+    use super::*;
+    use crate::config::{ListenConfig, MAIN_ZONE};
+
+    const PASSWORD: &str = "hackme";
+    /// Bounds the number of packets which are read while waiting for the chained stream.
+    const MAX_PACKETS: usize = 500;
+
+    /// The connection of a source as it is seen by the server.
+    /// The Ogg reader only seeks in order to skip invalid data, which is an error here.
+    struct Connection(TcpStream);
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Seek for Connection {
+        fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+            Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "the stream is not valid Ogg",
+            ))
+        }
+    }
+
+    /// Starts sending a listen stream to a server which has yet to accept the connection.
+    fn start() -> (TcpListener, Arc<ListenBus>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let config = IcecastConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            mount: "/radio.opus".to_string(),
+            username: "source".to_string(),
+            password: PASSWORD.to_string(),
+            zone: MAIN_ZONE.to_string(),
+            name: Some("Test Radio".to_string()),
+            description: None,
+            genre: Some("Jazz".to_string()),
+            public: true,
+        };
+        let bus = Arc::new(ListenBus::new(&ListenConfig { bitrate_kbps: 64 }).unwrap());
+        spawn(config, bus.clone());
+        (listener, bus)
+    }
+
+    /// Accepts the next connection of the source like Icecast does and returns its request header.
+    fn accept(listener: &TcpListener) -> (PacketReader<Connection>, String) {
+        let timeout = Duration::from_secs(ICECAST_TIMEOUT_SECS);
+        let start = Instant::now();
+        let mut stream = loop {
+            match listener.accept() {
+                Ok((stream, _)) => break stream,
+                Err(err) if err.kind() == ErrorKind::WouldBlock && start.elapsed() < timeout => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                Err(err) => panic!("the source did not connect: {err}"),
+            }
+        };
+        stream.set_nonblocking(false).unwrap();
+        stream.set_read_timeout(Some(timeout)).unwrap();
+
+        let mut request = vec![];
+        let mut byte = [0];
+        while !request.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            request.push(byte[0]);
+        }
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        (
+            PacketReader::new(Connection(stream)),
+            String::from_utf8(request).unwrap(),
+        )
+    }
+
+    /// Reads the next packet, the pages it is read from are checked by the Ogg reader.
+    fn read_packet(reader: &mut PacketReader<Connection>) -> Packet {
+        reader.read_packet_expected().unwrap()
+    }
+
+    /// Reads the header packets of a logical stream and returns its comment header.
+    fn read_headers(reader: &mut PacketReader<Connection>, serial: u32) -> Vec<u8> {
+        let head = read_packet(reader);
+        assert!(head.first_in_stream());
+        assert_eq!(head.stream_serial(), serial);
+        assert!(head.data.starts_with(b"OpusHead"));
+
+        let tags = read_packet(reader);
+        assert_eq!(tags.stream_serial(), serial);
+        assert!(tags.data.starts_with(b"OpusTags"));
+        tags.data
+    }
+
+    #[test]
+    fn sends_ogg_opus_stream_as_authenticated_source() {
+        let (listener, _bus) = start();
+
+        let (mut reader, request) = accept(&listener);
+
+        assert!(request.starts_with("PUT /radio.opus HTTP/1.1\r\n"));
+        let credentials = BASE64.encode(format!("source:{PASSWORD}"));
+        for header in [
+            format!("Authorization: Basic {credentials}"),
+            "Content-Type: audio/ogg".to_string(),
+            "Expect: 100-continue".to_string(),
+            "Ice-Public: 1".to_string(),
+            "Ice-Name: Test Radio".to_string(),
+            "Ice-Genre: Jazz".to_string(),
+        ] {
+            assert!(
+                request.contains(&format!("\r\n{header}\r\n")),
+                "`{header}` is missing in the request:\n{request}"
+            );
+        }
+        assert!(!request.contains("Ice-Description"));
+
+        read_headers(&mut reader, OGG_SERIAL);
+        let audio = read_packet(&mut reader);
+        assert_eq!(audio.stream_serial(), OGG_SERIAL);
+        assert!(!audio.data.is_empty());
+    }
+
+    #[test]
+    fn reconnects_after_server_dropped_connection() {
+        let (listener, _bus) = start();
+        let (reader, _) = accept(&listener);
+
+        drop(reader);
+
+        // the stream starts over on the new connection
+        let (mut reader, request) = accept(&listener);
+        assert!(request.starts_with("PUT /radio.opus HTTP/1.1\r\n"));
+        read_headers(&mut reader, OGG_SERIAL);
+    }
+
+    #[test]
+    fn chains_new_stream_when_title_changes() {
+        let (listener, bus) = start();
+        let (mut reader, _) = accept(&listener);
+        let tags = read_headers(&mut reader, OGG_SERIAL);
+        assert!(!tags.windows(6).any(|w| w == b"TITLE="));
+
+        bus.set_title("Jazz FM");
+
+        let last = (0..MAX_PACKETS)
+            .map(|_| read_packet(&mut reader))
+            .find(|p| p.last_in_stream())
+            .expect("the first stream did not end");
+        assert_eq!(last.stream_serial(), OGG_SERIAL);
+
+        let tags = read_headers(&mut reader, OGG_SERIAL + 1);
+        assert!(tags.ends_with(b"TITLE=Jazz FM"));
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::Result;
+use lofty::{Accessor, AudioFile, TaggedFileExt};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task};
+
+use crate::{audio::FILE_URL_PREFIX, config::Station};
+
+/// File extensions which are considered to be audio files during a scan.
+/// Each of these formats can be decoded by rodio.
+const AUDIO_EXTENSIONS: [&str; 4] = ["mp3", "flac", "ogg", "wav"];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Track {
+    /// The path of the file relative to the library root, also used as a stable ID
+    pub(crate) id: String,
+    pub(crate) artist: Option<String>,
+    pub(crate) album: Option<String>,
+    pub(crate) title: String,
+    #[serde(rename = "durationSecs")]
+    pub(crate) duration_secs: u64,
+    /// The modification time of the file (seconds since the UNIX epoch) when it was last scanned
+    pub(crate) modified: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Index {
+    tracks: Vec<Track>,
+}
+
+pub(crate) struct Library {
+    root: PathBuf,
+    index_path: PathBuf,
+    tracks: Vec<Track>,
+}
+
+impl Track {
+    fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        [
+            Some(&self.title),
+            self.artist.as_ref(),
+            self.album.as_ref(),
+            Some(&self.id),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|field| field.to_lowercase().contains(&query))
+    }
+}
+
+impl Library {
+    /// Loads the library using the index file at `index_path`.
+    /// If the index does not exist yet, the library starts out empty and is populated by the first scan.
+    pub(crate) fn new(root: PathBuf, index_path: PathBuf) -> Result<Self> {
+        let tracks = match index_path.exists() {
+            true => toml::from_str::<Index>(&fs::read_to_string(&index_path)?)?.tracks,
+            false => vec![],
+        };
+        Ok(Self {
+            root,
+            index_path,
+            tracks,
+        })
+    }
+
+    pub(crate) fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub(crate) fn track(&self, id: &str) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.id == id)
+    }
+
+    /// Returns the absolute path of the given track.
+    pub(crate) fn track_path(&self, track: &Track) -> PathBuf {
+        self.root.join(&track.id)
+    }
+
+    /// Creates a station which plays the given track once.
+    pub(crate) fn station(&self, track: &Track) -> Station {
+        Station {
+            id: "library".to_string(),
+            name: track.title.clone(),
+            description: track.artist.clone().unwrap_or_default(),
+            url: format!(
+                "{FILE_URL_PREFIX}{}",
+                self.track_path(track).to_string_lossy()
+            ),
+            image_file: PathBuf::from(""),
+            auto_restart: false,
+            auto_start: false,
+            gain_db: 0.0,
+            start_position: Duration::ZERO,
+        }
+    }
+
+    pub(crate) fn search(&self, query: &str) -> Vec<&Track> {
+        self.tracks.iter().filter(|t| t.matches(query)).collect()
+    }
+
+    /// Replaces the tracks of the library with the outcome of a scan and persists the index.
+    pub(crate) fn update(&mut self, tracks: Vec<Track>) -> Result<()> {
+        self.tracks = tracks;
+        let mut file = File::create(&self.index_path)?;
+        file.write_all(
+            toml::to_string_pretty(&Index {
+                tracks: self.tracks.clone(),
+            })
+            .unwrap()
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+/// Scans the `root` directory for audio files.
+/// Only files which are new or whose modification time changed since the `previous` scan are read again.
+pub(crate) fn scan(root: &Path, previous: &[Track]) -> Result<Vec<Track>> {
+    let previous: HashMap<&str, &Track> = previous.iter().map(|t| (t.id.as_str(), t)).collect();
+
+    let mut files = vec![];
+    collect_audio_files(root, &mut HashSet::new(), &mut files)?;
+
+    let mut tracks = Vec::with_capacity(files.len());
+    let (mut reused, mut read) = (0, 0);
+
+    for path in files {
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let id = relative.to_string_lossy().to_string();
+
+        // e.g. a symlink whose target has been removed
+        let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+            Ok(modified) => modified
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            Err(err) => {
+                warn!(
+                    "Skipping library file `{}`: could not read metadata: {err}",
+                    path.to_string_lossy()
+                );
+                continue;
+            }
+        };
+
+        match previous.get(id.as_str()) {
+            Some(track) if track.modified == modified => {
+                tracks.push((*track).clone());
+                reused += 1;
+            }
+            _ => match read_track(&path, id, modified) {
+                Ok(track) => {
+                    tracks.push(track);
+                    read += 1;
+                }
+                Err(err) => warn!(
+                    "Skipping library file `{}`: could not read tags: {err}",
+                    path.to_string_lossy()
+                ),
+            },
+        }
+    }
+
+    tracks.sort_by(|a, b| a.id.cmp(&b.id));
+    debug!(
+        "Library scan finished: {} tracks ({read} read, {reused} unchanged)",
+        tracks.len()
+    );
+
+    Ok(tracks)
+}
+
+/// Collects the audio files below `dir`. Symlinked directories are followed, but every directory
+/// is only visited once so that symlink loops do not recurse endlessly.
+fn collect_audio_files(
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if !visited.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        let is_dir = match file_type.is_symlink() {
+            // dangling symlinks are no directories, they are skipped once their metadata is read
+            true => fs::metadata(&path).is_ok_and(|m| m.is_dir()),
+            false => file_type.is_dir(),
+        };
+        if is_dir {
+            if let Err(err) = collect_audio_files(&path, visited, files) {
+                warn!(
+                    "Skipping library directory `{}`: {err}",
+                    path.to_string_lossy()
+                );
+            }
+            continue;
+        }
+        let is_audio = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.as_str()));
+        if is_audio {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_track(path: &Path, id: String, modified: u64) -> Result<Track> {
+    let tagged_file = lofty::read_from_path(path)?;
+    let duration_secs = tagged_file.properties().duration().as_secs();
+
+    // fall back to the file name if there is no title tag
+    let file_name = path
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| id.clone());
+
+    let track = match tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())
+    {
+        Some(tag) => Track {
+            id,
+            artist: tag.artist().map(|s| s.to_string()),
+            album: tag.album().map(|s| s.to_string()),
+            title: tag.title().map(|s| s.to_string()).unwrap_or(file_name),
+            duration_secs,
+            modified,
+        },
+        None => Track {
+            id,
+            artist: None,
+            album: None,
+            title: file_name,
+            duration_secs,
+            modified,
+        },
+    };
+
+    Ok(track)
+}
+
+/// Scans the library directory in the background and updates the index of the `library`.
+/// The lock is only held while reading the previous state and while applying the outcome of the scan.
+pub(crate) async fn rescan(library: &Mutex<Library>) -> Result<usize> {
+    let (root, previous) = {
+        let library = library.lock().await;
+        (library.root.clone(), library.tracks.clone())
+    };
+
+    let tracks = task::spawn_blocking(move || scan(&root, &previous)).await??;
+    let count = tracks.len();
+
+    library.lock().await.update(tracks)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn scan_skips_symlink_loops_and_dangling_symlinks() {
+        let root = std::env::temp_dir().join(format!("radio-library-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("album")).unwrap();
+
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(root.join("album/track.wav"), spec).unwrap();
+        for _ in 0..8000 {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        symlink(&root, root.join("album/loop")).unwrap();
+        symlink(root.join("missing.mp3"), root.join("dangling.mp3")).unwrap();
+
+        let tracks = scan(&root, &[]).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let ids: Vec<_> = tracks.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["album/track.wav"]);
+    }
+}
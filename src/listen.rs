@@ -0,0 +1,334 @@
+use bytes::Bytes;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus::{Application, Bitrate, Channels, Encoder};
+use rodio::{
+    dynamic_mixer::{self, DynamicMixerController},
+    Source,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{audio::Error, config::ListenConfig};
+
+/// Opus always encodes at 48 kHz.
+const LISTEN_SAMPLE_RATE: u32 = 48_000;
+const LISTEN_CHANNELS: u16 = 2;
+/// The duration of the audio in a single Opus packet.
+const OPUS_FRAME_MS: u64 = 20;
+/// The largest packet the encoder may produce, as recommended by the Opus documentation.
+const OPUS_MAX_PACKET_BYTES: usize = 4000;
+/// How many packets are collected in an Ogg page, fewer packets reduce the latency.
+const PACKETS_PER_PAGE: u32 = 5;
+/// How many packets a slow listener may fall behind before packets are skipped.
+const LISTEN_BUFFER_PACKETS: usize = 250;
+/// The serial number of the first logical stream of an Ogg container.
+pub const OGG_SERIAL: u32 = 0x5241_4449;
+/// The number of audio bytes between two ICY metadata blocks.
+pub const ICY_METAINT: usize = 16_000;
+/// Longer titles are cut off so that the metadata block stays below its limit of 4080 bytes.
+const ICY_MAX_TITLE_CHARS: usize = 1000;
+
+/// Encodes the audio played by a player as Opus and distributes the packets to all
+/// listeners. The audio is encoded once, no matter how many listeners there are.
+pub struct ListenBus {
+    mixer: Arc<DynamicMixerController<f32>>,
+    tx: broadcast::Sender<Bytes>,
+    /// The number of samples the decoder has to discard at the start of a stream
+    pre_skip: u16,
+    /// The name of the station which is currently played
+    title: Mutex<String>,
+    stop: Arc<AtomicBool>,
+}
+
+impl ListenBus {
+    pub fn new(config: &ListenConfig) -> Result<Self, Error> {
+        let mut encoder = Encoder::new(LISTEN_SAMPLE_RATE, Channels::Stereo, Application::Audio)?;
+        encoder.set_bitrate(Bitrate::Bits(config.bitrate_kbps as i32 * 1000))?;
+
+        let pre_skip = encoder.get_lookahead()? as u16;
+
+        let (mixer, mut source) = dynamic_mixer::mixer::<f32>(LISTEN_CHANNELS, LISTEN_SAMPLE_RATE);
+        let (tx, _) = broadcast::channel(LISTEN_BUFFER_PACKETS);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread_tx = tx.clone();
+        let thread_stop = stop.clone();
+        // the mixer is played in real time, just like a sound card would play it
+        thread::spawn(move || {
+            let frames_per_packet = LISTEN_SAMPLE_RATE as u64 * OPUS_FRAME_MS / 1000;
+            let mut buffer = vec![0.0; frames_per_packet as usize * LISTEN_CHANNELS as usize];
+            let mut packet = vec![0; OPUS_MAX_PACKET_BYTES];
+            let start = Instant::now();
+            let mut frames_played = 0;
+
+            while !thread_stop.load(AtomicOrdering::Relaxed) {
+                // an empty mixer returns nothing, which is played as silence
+                for sample in buffer.iter_mut() {
+                    *sample = source.next().unwrap_or(0.0);
+                }
+                frames_played += frames_per_packet;
+
+                // nothing is encoded while nobody is listening
+                if thread_tx.receiver_count() > 0 {
+                    match encoder.encode_float(&buffer, &mut packet) {
+                        // listeners may have disconnected in the meantime
+                        Ok(len) => _ = thread_tx.send(Bytes::copy_from_slice(&packet[..len])),
+                        Err(err) => error!("Could not encode listen stream: {err}"),
+                    }
+                }
+
+                let due =
+                    start + Duration::from_millis(frames_played * 1000 / LISTEN_SAMPLE_RATE as u64);
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+            }
+        });
+
+        Ok(Self {
+            mixer,
+            tx,
+            pre_skip,
+            title: Mutex::new(String::new()),
+            stop,
+        })
+    }
+
+    /// Adds a source which is played alongside the sources which are already playing,
+    /// e.g. during a crossfade.
+    pub fn add(&self, source: impl Source<Item = f32> + Send + 'static) {
+        self.mixer.add(source);
+    }
+
+    pub fn set_title(&self, title: &str) {
+        *self.title.lock().unwrap() = title.to_string();
+    }
+
+    pub fn title(&self) -> String {
+        self.title.lock().unwrap().clone()
+    }
+
+    /// Returns a new receiver of the Opus packets, starting with the next packet.
+    pub fn packets(&self) -> broadcast::Receiver<Bytes> {
+        self.tx.subscribe()
+    }
+
+    /// Returns a muxer for the packets of this bus.
+    pub fn muxer(&self) -> OggMuxer {
+        OggMuxer::new(self.pre_skip)
+    }
+
+    /// Returns a new listener which receives the stream as Ogg/Opus from the next packet on.
+    pub fn subscribe(self: &Arc<Self>, icy: bool) -> Listener {
+        let mut muxer = self.muxer();
+        Listener {
+            bus: self.clone(),
+            headers: Some(muxer.start(OGG_SERIAL, None)),
+            muxer,
+            rx: self.tx.subscribe(),
+            icy: icy.then(IcyMetadata::default),
+        }
+    }
+}
+
+impl Drop for ListenBus {
+    fn drop(&mut self) {
+        self.stop.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+/// Packs Opus packets into Ogg pages. A new logical stream can be started at any time,
+/// e.g. in order to change the metadata of the stream.
+pub struct OggMuxer {
+    writer: PacketWriter<Vec<u8>>,
+    pre_skip: u16,
+    serial: u32,
+    granule_position: u64,
+    packets_in_page: u32,
+}
+
+impl OggMuxer {
+    fn new(pre_skip: u16) -> Self {
+        Self {
+            writer: PacketWriter::new(vec![]),
+            pre_skip,
+            serial: OGG_SERIAL,
+            granule_position: 0,
+            packets_in_page: 0,
+        }
+    }
+
+    /// Returns the header pages of a new logical stream with the `title` as its comment.
+    pub fn start(&mut self, serial: u32, title: Option<&str>) -> Bytes {
+        self.serial = serial;
+        self.granule_position = 0;
+        self.packets_in_page = 0;
+        self.write(opus_head(self.pre_skip), PacketWriteEndInfo::EndPage);
+        self.write(opus_tags(title), PacketWriteEndInfo::EndPage);
+        self.take()
+    }
+
+    /// Adds the packet to the current page, returns the page once it is full.
+    pub fn push(&mut self, packet: &[u8]) -> Option<Bytes> {
+        self.packets_in_page += 1;
+        match self.packets_in_page == PACKETS_PER_PAGE {
+            true => {
+                self.packets_in_page = 0;
+                self.write_audio(packet, PacketWriteEndInfo::EndPage);
+                Some(self.take())
+            }
+            false => {
+                self.write_audio(packet, PacketWriteEndInfo::NormalPacket);
+                None
+            }
+        }
+    }
+
+    /// Adds the last packet of the logical stream, returns the remaining pages.
+    pub fn finish(&mut self, packet: &[u8]) -> Bytes {
+        self.write_audio(packet, PacketWriteEndInfo::EndStream);
+        self.take()
+    }
+
+    /// The serial of the current logical stream.
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    fn write_audio(&mut self, packet: &[u8], end: PacketWriteEndInfo) {
+        self.granule_position += LISTEN_SAMPLE_RATE as u64 * OPUS_FRAME_MS / 1000;
+        self.write(packet.into(), end);
+    }
+
+    fn write(&mut self, packet: Box<[u8]>, end: PacketWriteEndInfo) {
+        self.writer
+            .write_packet(packet, self.serial, end, self.granule_position)
+            .expect("writing to memory cannot fail");
+    }
+
+    fn take(&mut self) -> Bytes {
+        Bytes::from(std::mem::take(self.writer.inner_mut()))
+    }
+}
+
+/// The identification header of an Ogg/Opus stream (RFC 7845, section 5.1).
+fn opus_head(pre_skip: u16) -> Box<[u8]> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(LISTEN_CHANNELS as u8);
+    head.extend(pre_skip.to_le_bytes());
+    head.extend(LISTEN_SAMPLE_RATE.to_le_bytes());
+    head.extend(0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family: mono or stereo
+    head.into_boxed_slice()
+}
+
+/// The comment header of an Ogg/Opus stream (RFC 7845, section 5.2).
+fn opus_tags(title: Option<&str>) -> Box<[u8]> {
+    let vendor = concat!("radio ", env!("CARGO_PKG_VERSION"));
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend((vendor.len() as u32).to_le_bytes());
+    tags.extend(vendor.as_bytes());
+    match title {
+        Some(title) => {
+            let comment = format!("TITLE={title}");
+            tags.extend(1u32.to_le_bytes());
+            tags.extend((comment.len() as u32).to_le_bytes());
+            tags.extend(comment.as_bytes());
+        }
+        None => tags.extend(0u32.to_le_bytes()),
+    }
+    tags.into_boxed_slice()
+}
+
+/// A client of the listen stream.
+pub struct Listener {
+    bus: Arc<ListenBus>,
+    headers: Option<Bytes>,
+    muxer: OggMuxer,
+    rx: broadcast::Receiver<Bytes>,
+    icy: Option<IcyMetadata>,
+}
+
+impl Listener {
+    /// Waits for the next part of the stream, returns `None` once the stream has ended.
+    pub async fn next(&mut self) -> Option<Bytes> {
+        let data = match self.headers.take() {
+            Some(headers) => headers,
+            None => loop {
+                match self.rx.recv().await {
+                    Ok(packet) => {
+                        if let Some(page) = self.muxer.push(&packet) {
+                            break page;
+                        }
+                    }
+                    // a listener which cannot keep up continues with the latest packets
+                    Err(RecvError::Lagged(packets)) => {
+                        debug!("Listener fell behind, skipping {packets} packets")
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            },
+        };
+        Some(match &mut self.icy {
+            Some(icy) => icy.interleave(&data, &self.bus.title()),
+            None => data,
+        })
+    }
+}
+
+/// Inserts SHOUTcast style metadata blocks into the stream every [`ICY_METAINT`] bytes.
+#[derive(Default)]
+struct IcyMetadata {
+    /// The number of audio bytes since the previous metadata block
+    written: usize,
+    /// The title which was sent in the previous metadata block
+    sent_title: Option<String>,
+}
+
+impl IcyMetadata {
+    fn interleave(&mut self, mut data: &[u8], title: &str) -> Bytes {
+        let mut out = Vec::with_capacity(data.len() + 64);
+        while !data.is_empty() {
+            let len = data.len().min(ICY_METAINT - self.written);
+            out.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            self.written += len;
+
+            if self.written == ICY_METAINT {
+                self.written = 0;
+                out.extend(self.block(title));
+            }
+        }
+        Bytes::from(out)
+    }
+
+    /// The title is only sent when it has changed, otherwise the block is empty.
+    fn block(&mut self, title: &str) -> Vec<u8> {
+        if self.sent_title.as_deref() == Some(title) {
+            return vec![0];
+        }
+        self.sent_title = Some(title.to_string());
+
+        // quotes would end the title early
+        let title: String = title
+            .chars()
+            .take(ICY_MAX_TITLE_CHARS)
+            .map(|c| if c == '\'' { '’' } else { c })
+            .collect();
+        let mut text = format!("StreamTitle='{title}';").into_bytes();
+        // the length is given in multiples of 16 bytes using a single byte
+        let blocks = text.len().div_ceil(16);
+        text.resize(blocks * 16, 0);
+        let mut block = vec![blocks as u8];
+        block.extend(text);
+        block
+    }
+}
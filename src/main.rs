@@ -5,39 +5,69 @@ use actix_identity::IdentityMiddleware;
 use actix_session::{config::PersistentSession, storage::CookieSessionStore, SessionMiddleware};
 use actix_web::{
     cookie::{time::Duration, Key},
-    web::Data,
+    web::{self, Data},
     App, HttpServer,
 };
-use anyhow::Context;
+use anyhow::{bail, Context};
 use clap::Parser;
 use config::Config;
 use env_logger::Env;
-use settings::Settings;
-use tokio::sync::Mutex;
+use library::Library;
+use playlist::Playlists;
+use podcast::Podcasts;
+use tokio::{sync::Mutex, time};
 
 mod audio;
 mod cli;
 mod config;
 mod decoder;
+mod dsp;
+mod hotplug;
+mod icecast;
+mod library;
+mod listen;
+mod output;
+mod playlist;
+mod podcast;
 mod routes;
 mod settings;
+mod silence;
+mod zapping;
+mod zone;
 
 use crate::{
-    audio::Player,
     cli::{Args, Command},
+    config::MAIN_ZONE,
+    hotplug::DeviceWatcher,
+    zone::Zone,
 };
 
 #[macro_use]
 extern crate log;
 
 pub(crate) struct State {
-    player: Mutex<Player>,
+    /// The main zone comes first, followed by the configured zones
+    zones: Vec<Zone>,
     config: Config,
-    settings: Mutex<Settings>,
+    library: Option<Mutex<Library>>,
+    playlists: Mutex<Playlists>,
+    podcasts: Option<Mutex<Podcasts>>,
 }
 
 const CONFIG_PATH: &str = "./config.toml";
 const SETTINGS_PATH: &str = "./settings.toml";
+/// The directory containing the settings files of the zones other than the main zone.
+const ZONES_PATH: &str = "./zones";
+const LIBRARY_INDEX_PATH: &str = "./library.toml";
+const PLAYLISTS_PATH: &str = "./playlists.toml";
+const PODCASTS_PATH: &str = "./podcasts.toml";
+
+/// How often the playback position of podcast episodes is saved.
+const PODCAST_POSITION_SAVE_INTERVAL_SECS: u64 = 10;
+/// How often the output devices are enumerated in order to detect hotplugging.
+const DEVICE_WATCH_INTERVAL_SECS: u64 = 5;
+/// How often the compressor schedule is checked.
+const COMPRESSOR_SCHEDULE_INTERVAL_SECS: u64 = 30;
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
@@ -49,8 +79,7 @@ async fn main() -> anyhow::Result<()> {
         None => PathBuf::from(CONFIG_PATH),
     };
 
-    let settings = settings::read(&PathBuf::from(SETTINGS_PATH))
-        .with_context(|| format!("could not read or create settings file at `{SETTINGS_PATH}`"))?;
+    let settings = zone::read_settings(MAIN_ZONE)?;
 
     let config = match config::read(&config_path).with_context(|| {
         format!(
@@ -77,6 +106,12 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
+    let zone_settings = config
+        .zones
+        .iter()
+        .map(|zone| zone::read_settings(&zone.name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
     if args.subcommand != Command::Run {
         return Ok(());
     }
@@ -84,26 +119,163 @@ async fn main() -> anyhow::Result<()> {
     let key = Key::from(config.session_key.as_bytes());
     let port = config.port;
 
-    let mut player = Player::new(settings.volume_percent, settings.alsa_device_index)?;
-
-    match config.stations.iter().find(|s| s.auto_start) {
-        Some(station) => {
-            info!("Starting auto start stream with ID `{}`...", station.id);
-            player
-                .play(station.clone())
-                .await
-                .with_context(|| "could not start auto start stream")?;
-            info!("Successfully started stream `{}` as it is marked as auto start", station.id);
+    let device = match &args.output {
+        // every zone needs its own device, so a single device cannot replace all of them
+        Some(_) if !config.zones.is_empty() => {
+            bail!("the `--output` option cannot be used when zones are configured")
         }
-        None => {
-            debug!("No stream is marked as auto start, continuing normal startup...")
+        Some(output) => {
+            let device = output::parse_output(output).map_err(anyhow::Error::msg)?;
+            info!("Playing on `{output}` instead of the configured output device");
+            Some(device)
         }
+        None => None,
+    };
+
+    let mut zone_devices = vec![(MAIN_ZONE, &settings)];
+    zone_devices.extend(
+        config
+            .zones
+            .iter()
+            .map(|zone| zone.name.as_str())
+            .zip(&zone_settings),
+    );
+    zone::check_devices(&zone_devices)?;
+
+    let mut zones = vec![Zone::new(MAIN_ZONE, settings, &config, device)?];
+    for (zone, settings) in config.zones.iter().zip(zone_settings) {
+        zones.push(Zone::new(&zone.name, settings, &config, None)?);
+    }
+    for zone in &zones {
+        zone.auto_start(&config).await?;
+    }
+
+    if let Some(icecast_config) = &config.icecast {
+        let zone = zones
+            .iter()
+            .find(|zone| zone.name == icecast_config.zone)
+            .expect("the zone of the icecast output exists");
+        let bus = zone
+            .player
+            .lock()
+            .await
+            .listen()
+            .cloned()
+            .expect("listening is configured for the icecast output");
+        icecast::spawn(icecast_config.clone(), bus);
     }
 
+    let playlists = playlist::read(&PathBuf::from(PLAYLISTS_PATH)).with_context(|| {
+        format!("could not read or create playlists file at `{PLAYLISTS_PATH}`")
+    })?;
+
+    let library = match &config.library {
+        Some(library_config) => Some(Mutex::new(
+            Library::new(
+                library_config.path.clone(),
+                PathBuf::from(LIBRARY_INDEX_PATH),
+            )
+            .with_context(|| format!("could not read library index at `{LIBRARY_INDEX_PATH}`"))?,
+        )),
+        None => None,
+    };
+    let podcasts = match &config.podcasts {
+        Some(_) => Some(Mutex::new(
+            podcast::read(&PathBuf::from(PODCASTS_PATH)).with_context(|| {
+                format!("could not read or create podcasts file at `{PODCASTS_PATH}`")
+            })?,
+        )),
+        None => None,
+    };
+    let podcast_refresh_interval = config
+        .podcasts
+        .as_ref()
+        .map(|p| std::time::Duration::from_secs(p.refresh_interval_secs));
+
+    let library_rescan_interval = config
+        .library
+        .as_ref()
+        .map(|l| std::time::Duration::from_secs(l.rescan_interval_secs));
+
     let data = Data::new(State {
-        player: Mutex::new(player),
+        zones,
         config,
-        settings: Mutex::new(settings),
+        library,
+        playlists: Mutex::new(playlists),
+        podcasts,
+    });
+
+    if let Some(interval) = library_rescan_interval {
+        let data = data.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(interval);
+            loop {
+                interval.tick().await;
+                let library = data.library.as_ref().expect("library is configured");
+                match library::rescan(library).await {
+                    Ok(tracks) => debug!("Library rescan completed: {tracks} tracks indexed"),
+                    Err(err) => error!("Could not rescan library: {err:#}"),
+                }
+            }
+        });
+    }
+
+    if let Some(interval) = podcast_refresh_interval {
+        let refresh_data = data.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(interval);
+            loop {
+                interval.tick().await;
+                let podcasts = refresh_data
+                    .podcasts
+                    .as_ref()
+                    .expect("podcasts are configured");
+                match podcast::refresh(podcasts, &PathBuf::from(PODCASTS_PATH)).await {
+                    Ok(episodes) => debug!("Podcast refresh completed: {episodes} new episodes"),
+                    Err(err) => error!("Could not refresh podcasts: {err:#}"),
+                }
+            }
+        });
+
+        let position_data = data.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(std::time::Duration::from_secs(
+                PODCAST_POSITION_SAVE_INTERVAL_SECS,
+            ));
+            loop {
+                interval.tick().await;
+                save_podcast_position(&position_data).await;
+            }
+        });
+    }
+
+    let schedule_data = data.clone();
+    tokio::spawn(async move {
+        let mut interval = time::interval(std::time::Duration::from_secs(
+            COMPRESSOR_SCHEDULE_INTERVAL_SECS,
+        ));
+        loop {
+            interval.tick().await;
+            apply_compressor_schedule(&schedule_data).await;
+        }
+    });
+
+    let watch_data = data.clone();
+    tokio::spawn(async move {
+        // every zone may use a different host, so each has its own watcher
+        let mut watchers: Vec<_> = watch_data
+            .zones
+            .iter()
+            .map(|_| DeviceWatcher::default())
+            .collect();
+        let mut interval =
+            time::interval(std::time::Duration::from_secs(DEVICE_WATCH_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            for (zone, watcher) in watch_data.zones.iter().zip(&mut watchers) {
+                hotplug::watch(&watch_data, zone, watcher).await;
+            }
+        }
     });
 
     let server = HttpServer::new(move || {
@@ -125,15 +297,23 @@ async fn main() -> anyhow::Result<()> {
             // API endpoints
             .service(routes::post_login)
             .service(routes::logout)
-            .service(routes::get_status)
+            .service(routes::get_zones)
             .service(routes::get_stations)
-            .service(routes::post_play)
-            .service(routes::post_play_url)
-            .service(routes::post_stop)
-            .service(routes::post_volume)
-            .service(routes::get_devices)
-            .service(routes::post_device)
-            .service(routes::get_device)
+            .service(routes::get_library)
+            .service(routes::get_library_search)
+            .service(routes::post_library_scan)
+            .service(routes::get_playlists)
+            .service(routes::put_playlist)
+            .service(routes::delete_playlist)
+            .service(routes::get_podcasts)
+            .service(routes::post_podcast)
+            .service(routes::delete_podcast)
+            .service(routes::post_podcasts_refresh)
+            .service(routes::post_podcast_download)
+            .service(routes::post_podcast_discard)
+            // the routes of a zone come last as their scopes match every path below `/api`
+            .service(web::scope("/api/zones/{zone}").configure(routes::zone_routes))
+            .service(web::scope("/api").configure(routes::zone_routes))
     })
     .bind(("::0", port))
     .with_context(|| "could not start webserver")?;
@@ -149,3 +329,53 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Records the playback position of the podcast episodes which are currently playing.
+async fn save_podcast_position(data: &State) {
+    for zone in &data.zones {
+        let (url, position) = {
+            let mut player = zone.player.lock().await;
+            let url = match player.curr_station() {
+                Some(station) if station.id == "podcast" => station.url.clone(),
+                _ => continue,
+            };
+            match player.position() {
+                Some(position) => (url, position),
+                None => continue,
+            }
+        };
+
+        let podcasts = data.podcasts.as_ref().expect("podcasts are configured");
+        let mut podcasts = podcasts.lock().await;
+        if podcasts.record_position(&url, position) {
+            if let Err(err) = podcasts.write(&PathBuf::from(PODCASTS_PATH)) {
+                error!("Could not save podcast positions: {err:#}");
+            }
+        }
+    }
+}
+
+/// Switches the compressor preset of every zone when the time of day enters or leaves the
+/// schedule of the zone.
+async fn apply_compressor_schedule(data: &State) {
+    for zone in &data.zones {
+        let preset = {
+            let settings = zone.settings.lock().await;
+            if settings.compressor.schedule.is_none() {
+                continue;
+            }
+            settings
+                .compressor
+                .active_preset(chrono::Local::now().time())
+        };
+
+        let player = zone.player.lock().await;
+        if player.dsp().compressor().preset() != preset {
+            info!(
+                "Switching compressor of zone `{}` to preset `{preset:?}` due to the schedule",
+                zone.name
+            );
+            player.dsp().compressor().set(preset);
+        }
+    }
+}
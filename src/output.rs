@@ -0,0 +1,600 @@
+use rodio::{
+    cpal::{
+        self,
+        traits::{DeviceTrait, StreamTrait},
+        BufferSize, FromSample, SizedSample, StreamConfig, SupportedBufferSize,
+        SupportedStreamConfig,
+    },
+    dynamic_mixer::{self, DynamicMixerController},
+    StreamError,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    os::unix::fs::{FileTypeExt, OpenOptionsExt},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audio::{self, Error, OutputDevice},
+    config::{PcmFormat, PipeConfig},
+};
+
+/// The range of sample rates which can be requested.
+const SAMPLE_RATE_RANGE: std::ops::RangeInclusive<u32> = 8_000..=384_000;
+/// The sample rate of virtual outputs unless another one is set.
+const VIRTUAL_SAMPLE_RATE: u32 = 44_100;
+const VIRTUAL_CHANNELS: u16 = 2;
+/// How much audio is written to a virtual output at once.
+const VIRTUAL_WRITE_INTERVAL_MS: u64 = 20;
+/// The name of the only device of the null output.
+pub const NULL_DEVICE_NAME: &str = "null";
+/// The name of the only device of the pipe output.
+pub const PIPE_DEVICE_NAME: &str = "pipe";
+/// The pipe path which stands for stdout.
+const STDOUT_PATH: &str = "-";
+
+/// Mixes the sources which are played on an output stream, e.g. two stations during a crossfade.
+pub type Mixer = Arc<DynamicMixerController<f32>>;
+
+/// Outputs which do not play on an audio device, they are offered as additional audio hosts.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VirtualHost {
+    /// Discards the audio
+    Null,
+    /// Writes the audio to the WAV file at the path given as the device name
+    Wav,
+    /// Writes raw PCM to the pipe or stdout as configured in the `pipe` section of the config
+    Pipe,
+}
+
+impl VirtualHost {
+    pub const ALL: [VirtualHost; 3] = [VirtualHost::Null, VirtualHost::Wav, VirtualHost::Pipe];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VirtualHost::Null => "Null",
+            VirtualHost::Wav => "WAV",
+            VirtualHost::Pipe => "Pipe",
+        }
+    }
+
+    /// Returns the virtual host with the given name (case insensitive).
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|host| host.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Returns whether the device is provided by a virtual host.
+    pub fn is_virtual(device: &OutputDevice) -> bool {
+        device.host.as_deref().and_then(Self::from_name).is_some()
+    }
+
+    /// Returns whether the device may be used by several zones at once.
+    /// Only the null output can be shared, as it discards the audio.
+    pub fn is_shareable(device: &OutputDevice) -> bool {
+        device.host.as_deref().and_then(Self::from_name) == Some(VirtualHost::Null)
+    }
+
+    /// Returns the names of the devices which can be listed.
+    /// WAV files are not listed as any path can be used.
+    pub fn device_names(self) -> Vec<String> {
+        match self {
+            VirtualHost::Null => vec![NULL_DEVICE_NAME.to_string()],
+            VirtualHost::Wav => vec![],
+            VirtualHost::Pipe => vec![PIPE_DEVICE_NAME.to_string()],
+        }
+    }
+}
+
+/// Returns the only device of the null output.
+pub fn null_device() -> OutputDevice {
+    OutputDevice {
+        host: Some(VirtualHost::Null.name().to_string()),
+        name: NULL_DEVICE_NAME.to_string(),
+        index: 0,
+    }
+}
+
+/// Returns the only device of the pipe output.
+pub fn pipe_device() -> OutputDevice {
+    OutputDevice {
+        host: Some(VirtualHost::Pipe.name().to_string()),
+        name: PIPE_DEVICE_NAME.to_string(),
+        index: 0,
+    }
+}
+
+/// Parses an output given on the command line: `null`, `pipe` or `wav:<path>`.
+pub fn parse_output(output: &str) -> Result<OutputDevice, String> {
+    if output.eq_ignore_ascii_case(NULL_DEVICE_NAME) {
+        return Ok(null_device());
+    }
+    if output.eq_ignore_ascii_case(PIPE_DEVICE_NAME) {
+        return Ok(pipe_device());
+    }
+    match output.split_once(':') {
+        Some((host, path)) if host.eq_ignore_ascii_case("wav") && !path.is_empty() => {
+            Ok(OutputDevice {
+                host: Some(VirtualHost::Wav.name().to_string()),
+                name: path.to_string(),
+                index: 0,
+            })
+        }
+        _ => Err(format!(
+            "invalid output `{output}`: expected `null`, `pipe` or `wav:<path>`"
+        )),
+    }
+}
+
+/// The configuration of the stream which is opened on the output device.
+/// Unset values are taken from the default configuration of the device.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct StreamSettings {
+    /// The number of frames the device requests at once, small buffers reduce the latency
+    /// while large buffers prevent underruns on slow machines
+    #[serde(default, rename = "bufferFrames")]
+    pub buffer_frames: Option<u32>,
+    /// The preferred sample rate, streams with another sample rate are resampled
+    #[serde(default, rename = "sampleRate")]
+    pub sample_rate: Option<u32>,
+    #[serde(default, rename = "sampleFormat")]
+    pub sample_format: Option<SampleFormat>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleFormat {
+    I16,
+    U16,
+    I32,
+    F32,
+}
+
+impl SampleFormat {
+    fn to_cpal(self) -> cpal::SampleFormat {
+        match self {
+            SampleFormat::I16 => cpal::SampleFormat::I16,
+            SampleFormat::U16 => cpal::SampleFormat::U16,
+            SampleFormat::I32 => cpal::SampleFormat::I32,
+            SampleFormat::F32 => cpal::SampleFormat::F32,
+        }
+    }
+}
+
+impl StreamSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.buffer_frames == Some(0) {
+            return Err("buffer size must be > 0 frames".to_string());
+        }
+        if let Some(rate) = self.sample_rate {
+            if !SAMPLE_RATE_RANGE.contains(&rate) {
+                return Err(format!(
+                    "sample rate {rate} Hz is not within {} Hz and {} Hz",
+                    SAMPLE_RATE_RANGE.start(),
+                    SAMPLE_RATE_RANGE.end()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A stream on an output device which plays everything added to its mixer.
+/// Playback ends once the stream is dropped.
+pub struct OutputStream {
+    _stream: Stream,
+    failed: Arc<AtomicBool>,
+}
+
+enum Stream {
+    Device { _stream: cpal::Stream },
+    Virtual { _stream: VirtualStream },
+}
+
+impl OutputStream {
+    /// Opens a stream on the device using the `settings` where the device supports them.
+    /// Returns the mixer whose sources are played on the stream and the number of channels of
+    /// the stream. The pipe output uses the format of the `pipe` config instead of the `settings`.
+    pub fn open(
+        output_device: &OutputDevice,
+        settings: &StreamSettings,
+        pipe: Option<&PipeConfig>,
+    ) -> Result<(Self, Mixer, u16), Error> {
+        match output_device
+            .host
+            .as_deref()
+            .and_then(VirtualHost::from_name)
+        {
+            Some(host) => Self::open_virtual(host, output_device, settings, pipe),
+            None => Self::open_device(output_device, settings),
+        }
+    }
+
+    /// Returns the flag which is raised once the stream can no longer play.
+    /// Only virtual outputs fail, e.g. when the reader of the pipe has gone away.
+    pub fn failed(&self) -> Arc<AtomicBool> {
+        self.failed.clone()
+    }
+
+    fn open_virtual(
+        host: VirtualHost,
+        output_device: &OutputDevice,
+        settings: &StreamSettings,
+        pipe: Option<&PipeConfig>,
+    ) -> Result<(Self, Mixer, u16), Error> {
+        let mut sample_rate = settings.sample_rate.unwrap_or(VIRTUAL_SAMPLE_RATE);
+        let mut channels = VIRTUAL_CHANNELS;
+        let writer: Box<dyn SampleWriter> = match host {
+            VirtualHost::Null => Box::new(NullWriter),
+            VirtualHost::Pipe => {
+                let Some(pipe) = pipe else {
+                    return Err(Error::NoPipeOutput);
+                };
+                sample_rate = pipe.sample_rate;
+                channels = pipe.channels;
+                Box::new(PipeWriter {
+                    out: open_pipe(&pipe.path)?,
+                    format: pipe.format,
+                    bytes: vec![],
+                })
+            }
+            VirtualHost::Wav => {
+                let float = settings.sample_format == Some(SampleFormat::F32);
+                let spec = hound::WavSpec {
+                    channels,
+                    sample_rate,
+                    bits_per_sample: if float { 32 } else { 16 },
+                    sample_format: match float {
+                        true => hound::SampleFormat::Float,
+                        false => hound::SampleFormat::Int,
+                    },
+                };
+                let writer = hound::WavWriter::create(&output_device.name, spec).map_err(
+                    |err| match err {
+                        hound::Error::IoError(err) => err,
+                        err => io::Error::other(err),
+                    },
+                )?;
+                Box::new(WavWriter { writer, float })
+            }
+        };
+
+        let (mixer, source) = dynamic_mixer::mixer(channels, sample_rate);
+        let failed = Arc::new(AtomicBool::new(false));
+        let stream = VirtualStream::spawn(source, channels, sample_rate, writer, failed.clone());
+
+        debug!(
+            "Opened {} output `{}` with {channels} channels at {sample_rate} Hz",
+            host.name(),
+            output_device.name
+        );
+        Ok((
+            Self {
+                _stream: Stream::Virtual { _stream: stream },
+                failed,
+            },
+            mixer,
+            channels,
+        ))
+    }
+
+    fn open_device(
+        output_device: &OutputDevice,
+        settings: &StreamSettings,
+    ) -> Result<(Self, Mixer, u16), Error> {
+        let device = audio::find_device(output_device)?;
+        let config = choose_config(&device, settings)?;
+        let mut stream_config = config.config();
+
+        if let Some(frames) = settings.buffer_frames {
+            let supported = match config.buffer_size() {
+                SupportedBufferSize::Range { min, max } => frames.clamp(*min, *max),
+                SupportedBufferSize::Unknown => frames,
+            };
+            if supported != frames {
+                warn!("Buffer size of {frames} frames is not supported, using {supported} frames");
+            }
+            stream_config.buffer_size = BufferSize::Fixed(supported);
+        }
+
+        let (stream, mixer) = match build_stream(&device, &stream_config, config.sample_format()) {
+            Ok(outcome) => outcome,
+            // some hosts do not report which buffer sizes they support
+            Err(err) if stream_config.buffer_size != BufferSize::Default => {
+                warn!("Could not use a fixed buffer size, using the default size: {err}");
+                stream_config.buffer_size = BufferSize::Default;
+                build_stream(&device, &stream_config, config.sample_format())
+                    .map_err(StreamError::BuildStreamError)?
+            }
+            Err(err) => return Err(StreamError::BuildStreamError(err).into()),
+        };
+        stream.play().map_err(StreamError::PlayStreamError)?;
+
+        debug!(
+            "Opened output stream with {} channels at {} Hz ({}, buffer: {:?})",
+            stream_config.channels,
+            stream_config.sample_rate.0,
+            config.sample_format(),
+            stream_config.buffer_size
+        );
+        Ok((
+            Self {
+                _stream: Stream::Device { _stream: stream },
+                failed: Arc::new(AtomicBool::new(false)),
+            },
+            mixer,
+            stream_config.channels,
+        ))
+    }
+}
+
+/// Selects the supported config which matches the sample rate and format of the `settings`.
+/// If the device does not support them, its default config is used.
+fn choose_config(
+    device: &cpal::Device,
+    settings: &StreamSettings,
+) -> Result<SupportedStreamConfig, Error> {
+    let default = device
+        .default_output_config()
+        .map_err(StreamError::DefaultStreamConfigError)?;
+    if settings.sample_rate.is_none() && settings.sample_format.is_none() {
+        return Ok(default);
+    }
+
+    let rate = settings
+        .sample_rate
+        .map(cpal::SampleRate)
+        .unwrap_or(default.sample_rate());
+    let format = settings
+        .sample_format
+        .map(SampleFormat::to_cpal)
+        .unwrap_or(default.sample_format());
+
+    let mut configs: Vec<_> = device
+        .supported_output_configs()
+        .map_err(StreamError::SupportedStreamConfigsError)?
+        .filter(|c| {
+            c.sample_format() == format
+                && c.min_sample_rate() <= rate
+                && rate <= c.max_sample_rate()
+        })
+        .collect();
+    // the number of channels is not configurable, so the default one is preferred
+    configs.sort_by_key(|c| c.channels() != default.channels());
+
+    match configs.into_iter().next() {
+        Some(config) => Ok(config.with_sample_rate(rate)),
+        None => {
+            warn!(
+                "Output device does not support {} Hz using {format}, using its default config",
+                rate.0
+            );
+            Ok(default)
+        }
+    }
+}
+
+fn build_stream(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    format: cpal::SampleFormat,
+) -> Result<(cpal::Stream, Mixer), cpal::BuildStreamError> {
+    // all sources are converted to the channels and sample rate of the stream
+    let (mixer, source) = dynamic_mixer::mixer(config.channels, config.sample_rate.0);
+
+    let stream = match format {
+        cpal::SampleFormat::I8 => build_typed_stream::<i8>(device, config, source),
+        cpal::SampleFormat::U8 => build_typed_stream::<u8>(device, config, source),
+        cpal::SampleFormat::I16 => build_typed_stream::<i16>(device, config, source),
+        cpal::SampleFormat::U16 => build_typed_stream::<u16>(device, config, source),
+        cpal::SampleFormat::I32 => build_typed_stream::<i32>(device, config, source),
+        cpal::SampleFormat::U32 => build_typed_stream::<u32>(device, config, source),
+        cpal::SampleFormat::F32 => build_typed_stream::<f32>(device, config, source),
+        cpal::SampleFormat::F64 => build_typed_stream::<f64>(device, config, source),
+        _ => Err(cpal::BuildStreamError::StreamConfigNotSupported),
+    }?;
+    Ok((stream, mixer))
+}
+
+fn build_typed_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut source: impl Iterator<Item = f32> + Send + 'static,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    device.build_output_stream::<T, _, _>(
+        config,
+        move |data, _| {
+            // an empty mixer returns nothing, which is played as silence
+            for sample in data.iter_mut() {
+                *sample = T::from_sample(source.next().unwrap_or(0.0));
+            }
+        },
+        |err| error!("An error occurred on the output stream: {err}"),
+        None,
+    )
+}
+
+/// Receives the samples which are played on a virtual output.
+trait SampleWriter: Send {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    /// Whether nothing is written while nothing plays, otherwise silence is written.
+    fn skips_silence(&self) -> bool {
+        false
+    }
+}
+
+struct NullWriter;
+
+impl SampleWriter for NullWriter {
+    fn write(&mut self, _samples: &[f32]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct WavWriter {
+    writer: hound::WavWriter<BufWriter<File>>,
+    float: bool,
+}
+
+impl SampleWriter for WavWriter {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let written = match self.float {
+                true => self.writer.write_sample(*sample),
+                false => self
+                    .writer
+                    .write_sample(<i16 as cpal::Sample>::from_sample(*sample)),
+            };
+            if let Err(err) = written {
+                return Err(io::Error::other(err));
+            }
+        }
+        // the output is kept open while the server runs, so the header is updated with every
+        // write in order to keep the file valid when the server is terminated
+        self.writer.flush().map_err(io::Error::other)
+    }
+
+    /// The recording only contains what was played, so that the file does not grow while stopped.
+    fn skips_silence(&self) -> bool {
+        true
+    }
+}
+
+/// Opens the pipe at `path` for writing, `-` opens stdout.
+/// Named pipes have to be opened by a reader beforehand, any other path is created or truncated
+/// as a regular file.
+fn open_pipe(path: &Path) -> Result<Box<dyn Write + Send>, Error> {
+    if path == Path::new(STDOUT_PATH) {
+        return Ok(Box::new(io::stdout()));
+    }
+    let is_fifo = fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo());
+    if !is_fifo {
+        return Ok(Box::new(File::create(path)?));
+    }
+
+    // opening a named pipe blocks until there is a reader, unless it is opened non-blocking
+    match OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(_) => {}
+        Err(err) if err.raw_os_error() == Some(libc::ENXIO) => {
+            return Err(Error::NoPipeReader(path.to_string_lossy().to_string()))
+        }
+        Err(err) => return Err(err.into()),
+    }
+    // there is a reader now, so opening the pipe for blocking writes returns immediately
+    Ok(Box::new(OpenOptions::new().write(true).open(path)?))
+}
+
+struct PipeWriter {
+    out: Box<dyn Write + Send>,
+    format: PcmFormat,
+    /// The encoded samples, reused between writes
+    bytes: Vec<u8>,
+}
+
+impl SampleWriter for PipeWriter {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        self.bytes.clear();
+        for sample in samples {
+            match self.format {
+                PcmFormat::S16le => self
+                    .bytes
+                    .extend(<i16 as cpal::Sample>::from_sample(*sample).to_le_bytes()),
+                PcmFormat::S16be => self
+                    .bytes
+                    .extend(<i16 as cpal::Sample>::from_sample(*sample).to_be_bytes()),
+                PcmFormat::S32le => self
+                    .bytes
+                    .extend(<i32 as cpal::Sample>::from_sample(*sample).to_le_bytes()),
+                PcmFormat::F32le => self.bytes.extend(sample.to_le_bytes()),
+            }
+        }
+        self.out.write_all(&self.bytes)?;
+        self.out.flush()
+    }
+}
+
+/// Plays the samples on a virtual output in real time, just like an audio device would.
+/// The thread stops once the stream is dropped.
+struct VirtualStream {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl VirtualStream {
+    fn spawn(
+        mut source: impl Iterator<Item = f32> + Send + 'static,
+        channels: u16,
+        sample_rate: u32,
+        mut writer: Box<dyn SampleWriter>,
+        failed: Arc<AtomicBool>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        let handle = thread::spawn(move || {
+            let frames_per_write = sample_rate as u64 * VIRTUAL_WRITE_INTERVAL_MS / 1000;
+            let mut buffer = vec![0.0; frames_per_write as usize * channels as usize];
+            let start = Instant::now();
+            let mut frames_written = 0;
+
+            while !thread_stop.load(AtomicOrdering::Relaxed) {
+                // an empty mixer returns nothing, which is played as silence
+                let mut playing = false;
+                for sample in buffer.iter_mut() {
+                    *sample = match source.next() {
+                        Some(sample) => {
+                            playing = true;
+                            sample
+                        }
+                        None => 0.0,
+                    };
+                }
+                if playing || !writer.skips_silence() {
+                    if let Err(err) = writer.write(&buffer) {
+                        error!("Could not write to virtual output: {err}");
+                        failed.store(true, AtomicOrdering::Relaxed);
+                        break;
+                    }
+                }
+                frames_written += frames_per_write;
+
+                let due = start + Duration::from_millis(frames_written * 1000 / sample_rate as u64);
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+            }
+        });
+
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for VirtualStream {
+    fn drop(&mut self) {
+        self.stop.store(true, AtomicOrdering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            // the writer is finalized when the thread ends
+            let _ = handle.join();
+        }
+    }
+}
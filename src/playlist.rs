@@ -0,0 +1,100 @@
+use anyhow::Result;
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A reference to something which can be played.
+/// References are resolved to a playable station when they are added to the queue.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum MediaRef {
+    /// A station of the configuration
+    Station { id: String },
+    /// A track of the local music library
+    Track { id: String },
+    /// An arbitrary HTTP(S) MP3 stream
+    Url { url: String },
+    /// An episode of a podcast subscription
+    #[serde(rename_all = "camelCase")]
+    Episode {
+        podcast_id: String,
+        episode_id: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Playlist {
+    pub(crate) name: String,
+    pub(crate) items: Vec<MediaRef>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Playlists {
+    #[serde(default)]
+    playlists: Vec<Playlist>,
+}
+
+impl Playlists {
+    pub(crate) fn all(&self) -> &[Playlist] {
+        &self.playlists
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&Playlist> {
+        self.playlists.iter().find(|p| p.name == name)
+    }
+
+    /// Saves the playlist, replacing an existing playlist with the same name.
+    pub(crate) fn save(&mut self, playlist: Playlist) {
+        match self.playlists.iter_mut().find(|p| p.name == playlist.name) {
+            Some(existing) => *existing = playlist,
+            None => self.playlists.push(playlist),
+        }
+    }
+
+    /// Removes the playlist with the given name and returns whether it existed.
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        let len = self.playlists.len();
+        self.playlists.retain(|p| p.name != name);
+        self.playlists.len() != len
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        match self.write_to_file(path) {
+            Ok(_) => {
+                trace!("Successfully written to playlists file");
+                Ok(())
+            }
+            Err(err) => {
+                error!(
+                    "Could not write to playlists file at `{}`: {err}",
+                    path.to_string_lossy()
+                );
+                Err(err)
+            }
+        }
+    }
+
+    fn write_to_file(&self, path: &Path) -> Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(toml::to_string_pretty(self).unwrap().as_bytes())?;
+        Ok(())
+    }
+}
+
+pub(crate) fn read(path: &Path) -> Result<Playlists> {
+    match path.exists() {
+        true => {
+            let raw_playlists = fs::read_to_string(path)?;
+            Ok(toml::from_str::<Playlists>(&raw_playlists)?)
+        }
+        false => {
+            let playlists = Playlists::default();
+            playlists.write_to_file(path)?;
+            Ok(playlists)
+        }
+    }
+}
@@ -0,0 +1,670 @@
+use anyhow::{bail, Context, Result};
+use reqwest::header::CONTENT_TYPE;
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task};
+
+use crate::{audio::FILE_URL_PREFIX, config::Station};
+
+/// If an episode is stopped within this many seconds before its end, it is considered finished.
+const EPISODE_FINISHED_THRESHOLD_SECS: u64 = 30;
+/// Longer episode IDs (often URLs) are cut off in the names of downloaded files.
+const MAX_FILE_SLUG_CHARS: usize = 64;
+/// The extension of downloaded episodes whose format cannot be determined.
+const DEFAULT_EXTENSION: &str = "mp3";
+
+/// The extensions of the formats podcasts are published in, along with their content types.
+const EPISODE_FORMATS: [(&str, &[&str]); 6] = [
+    ("mp3", &["audio/mpeg", "audio/mp3"]),
+    ("m4a", &["audio/mp4", "audio/x-m4a", "audio/aac"]),
+    ("ogg", &["audio/ogg", "application/ogg"]),
+    ("opus", &["audio/opus"]),
+    ("flac", &["audio/flac", "audio/x-flac"]),
+    ("wav", &["audio/wav", "audio/x-wav", "audio/wave"]),
+];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Episode {
+    /// The GUID of the episode as specified by the feed
+    pub(crate) id: String,
+    pub(crate) title: String,
+    pub(crate) url: String,
+    /// The publication date (seconds since the UNIX epoch)
+    pub(crate) published: Option<i64>,
+    #[serde(rename = "durationSecs")]
+    pub(crate) duration_secs: Option<u64>,
+    /// The position (in seconds) at which playback is resumed
+    #[serde(rename = "positionSecs")]
+    pub(crate) position_secs: u64,
+    pub(crate) finished: bool,
+    #[serde(rename = "cachedFile")]
+    pub(crate) cached_file: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Subscription {
+    pub(crate) id: String,
+    pub(crate) title: String,
+    pub(crate) url: String,
+    pub(crate) episodes: Vec<Episode>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct Podcasts {
+    #[serde(default)]
+    subscriptions: Vec<Subscription>,
+}
+
+impl Episode {
+    /// The URL which is used for playback, cached episodes are played from the local file.
+    fn playback_url(&self) -> String {
+        match &self.cached_file {
+            Some(path) => format!("{FILE_URL_PREFIX}{}", path.to_string_lossy()),
+            None => self.url.clone(),
+        }
+    }
+
+    /// Creates a station which plays this episode, starting at the saved position.
+    pub(crate) fn station(&self) -> Station {
+        Station {
+            id: "podcast".to_string(),
+            name: self.title.clone(),
+            description: "A podcast episode".to_string(),
+            url: self.playback_url(),
+            image_file: PathBuf::from(""),
+            auto_restart: false,
+            auto_start: false,
+            gain_db: 0.0,
+            start_position: Duration::from_secs(match self.finished {
+                true => 0,
+                false => self.position_secs,
+            }),
+        }
+    }
+}
+
+impl Podcasts {
+    pub(crate) fn subscriptions(&self) -> &[Subscription] {
+        &self.subscriptions
+    }
+
+    pub(crate) fn subscription(&self, id: &str) -> Option<&Subscription> {
+        self.subscriptions.iter().find(|s| s.id == id)
+    }
+
+    pub(crate) fn episode(&self, podcast_id: &str, episode_id: &str) -> Option<&Episode> {
+        self.subscription(podcast_id)?
+            .episodes
+            .iter()
+            .find(|e| e.id == episode_id)
+    }
+
+    fn episode_mut(&mut self, podcast_id: &str, episode_id: &str) -> Option<&mut Episode> {
+        self.subscriptions
+            .iter_mut()
+            .find(|s| s.id == podcast_id)?
+            .episodes
+            .iter_mut()
+            .find(|e| e.id == episode_id)
+    }
+
+    /// Adds a new subscription and returns its ID.
+    /// The ID is derived from the title of the feed.
+    pub(crate) fn subscribe(&mut self, url: String, feed: ParsedFeed) -> Result<String> {
+        if self.subscriptions.iter().any(|s| s.url == url) {
+            bail!("already subscribed to this feed")
+        }
+
+        let slug = slugify(&feed.title);
+        let mut id = slug.clone();
+        let mut suffix = 1;
+        while self.subscription(&id).is_some() {
+            suffix += 1;
+            id = format!("{slug}-{suffix}");
+        }
+
+        self.subscriptions.push(Subscription {
+            id: id.clone(),
+            title: feed.title,
+            url,
+            episodes: feed.episodes,
+        });
+        Ok(id)
+    }
+
+    /// Removes the subscription including all cached episodes.
+    /// Returns whether the subscription existed.
+    pub(crate) fn unsubscribe(&mut self, id: &str) -> bool {
+        let Some(idx) = self.subscriptions.iter().position(|s| s.id == id) else {
+            return false;
+        };
+        for episode in self.subscriptions.remove(idx).episodes {
+            remove_cached_file(&episode);
+        }
+        true
+    }
+
+    /// Merges freshly fetched feed data into the subscription.
+    /// New episodes are added, the playback state of known episodes is retained.
+    pub(crate) fn merge(&mut self, id: &str, feed: ParsedFeed) -> usize {
+        let Some(subscription) = self.subscriptions.iter_mut().find(|s| s.id == id) else {
+            return 0;
+        };
+        subscription.title = feed.title;
+
+        let mut new_episodes = 0;
+        let mut episodes = Vec::with_capacity(feed.episodes.len());
+        for mut episode in feed.episodes {
+            match subscription.episodes.iter().find(|e| e.id == episode.id) {
+                Some(known) => {
+                    episode.position_secs = known.position_secs;
+                    episode.finished = known.finished;
+                    episode.cached_file = known.cached_file.clone();
+                }
+                None => new_episodes += 1,
+            }
+            episodes.push(episode);
+        }
+
+        // episodes which disappeared from the feed are kept as long as they are cached
+        for known in &subscription.episodes {
+            if known.cached_file.is_some() && !episodes.iter().any(|e| e.id == known.id) {
+                episodes.push(known.clone());
+            }
+        }
+
+        subscription.episodes = episodes;
+        new_episodes
+    }
+
+    pub(crate) fn set_cached_file(&mut self, podcast_id: &str, episode_id: &str, path: PathBuf) {
+        if let Some(episode) = self.episode_mut(podcast_id, episode_id) {
+            episode.cached_file = Some(path)
+        }
+    }
+
+    /// Deletes the cached file of an episode.
+    /// Returns whether the episode was cached.
+    pub(crate) fn remove_cached(&mut self, podcast_id: &str, episode_id: &str) -> bool {
+        match self.episode_mut(podcast_id, episode_id) {
+            Some(episode) if episode.cached_file.is_some() => {
+                remove_cached_file(episode);
+                episode.cached_file = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records the playback position of the episode which is played using `playback_url`.
+    /// The episode may have been downloaded since playback started, so the URL of the feed
+    /// is matched as well.
+    /// Returns whether an episode was updated.
+    pub(crate) fn record_position(&mut self, playback_url: &str, position: Duration) -> bool {
+        let Some(episode) = self
+            .subscriptions
+            .iter_mut()
+            .flat_map(|s| s.episodes.iter_mut())
+            .find(|e| e.url == playback_url || e.playback_url() == playback_url)
+        else {
+            return false;
+        };
+
+        let position_secs = position.as_secs();
+        let finished = episode
+            .duration_secs
+            .is_some_and(|d| position_secs + EPISODE_FINISHED_THRESHOLD_SECS >= d);
+
+        if episode.position_secs == position_secs && episode.finished == finished {
+            return false;
+        }
+        episode.position_secs = position_secs;
+        episode.finished = finished;
+        true
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        match self.write_to_file(path) {
+            Ok(_) => {
+                trace!("Successfully written to podcasts file");
+                Ok(())
+            }
+            Err(err) => {
+                error!(
+                    "Could not write to podcasts file at `{}`: {err}",
+                    path.to_string_lossy()
+                );
+                Err(err)
+            }
+        }
+    }
+
+    fn write_to_file(&self, path: &Path) -> Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(toml::to_string_pretty(self).unwrap().as_bytes())?;
+        Ok(())
+    }
+}
+
+pub(crate) fn read(path: &Path) -> Result<Podcasts> {
+    match path.exists() {
+        true => {
+            let raw_podcasts = fs::read_to_string(path)?;
+            Ok(toml::from_str::<Podcasts>(&raw_podcasts)?)
+        }
+        false => {
+            let podcasts = Podcasts::default();
+            podcasts.write_to_file(path)?;
+            Ok(podcasts)
+        }
+    }
+}
+
+fn remove_cached_file(episode: &Episode) {
+    if let Some(path) = &episode.cached_file {
+        if let Err(err) = fs::remove_file(path) {
+            warn!(
+                "Could not remove cached episode `{}`: {err}",
+                path.to_string_lossy()
+            )
+        }
+    }
+}
+
+/// Returns the name of the downloaded file of an episode.
+/// The hash of the ID keeps episodes apart whose IDs only differ in characters which are
+/// removed from the slug.
+fn cached_file_name(episode_id: &str, extension: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    episode_id.hash(&mut hasher);
+    let slug: String = slugify(episode_id)
+        .chars()
+        .take(MAX_FILE_SLUG_CHARS)
+        .collect();
+    format!("{slug}-{:016x}.{extension}", hasher.finish())
+}
+
+/// Returns the file extension of an episode, taken from its URL or from its content type.
+fn episode_extension(url: &str, content_type: Option<&str>) -> &'static str {
+    let from_url = reqwest::Url::parse(url).ok().and_then(|url| {
+        let extension = Path::new(url.path()).extension()?.to_str()?.to_lowercase();
+        EPISODE_FORMATS
+            .iter()
+            .find(|e| e.0 == extension)
+            .map(|e| e.0)
+    });
+    let from_content_type = || {
+        // parameters such as the charset are ignored
+        let mime = content_type?.split(';').next()?.trim().to_lowercase();
+        EPISODE_FORMATS
+            .iter()
+            .find(|e| e.1.contains(&mime.as_str()))
+            .map(|e| e.0)
+    };
+    from_url
+        .or_else(from_content_type)
+        .unwrap_or(DEFAULT_EXTENSION)
+}
+
+fn slugify(title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    match slug.is_empty() {
+        true => "podcast".to_string(),
+        false => slug,
+    }
+}
+
+pub(crate) struct ParsedFeed {
+    title: String,
+    episodes: Vec<Episode>,
+}
+
+/// Fetches and parses the RSS or Atom feed at `url`.
+/// Entries without an audio enclosure are ignored.
+pub(crate) async fn fetch_feed(url: &str) -> Result<ParsedFeed> {
+    let body = reqwest::get(url).await?.error_for_status()?.bytes().await?;
+    let feed = feed_rs::parser::parse(&body[..]).with_context(|| "could not parse feed")?;
+
+    let episodes = feed
+        .entries
+        .into_iter()
+        .filter_map(|entry| {
+            let media = entry.media.iter().flat_map(|m| m.content.iter()).next();
+            let url = match media.and_then(|c| c.url.as_ref()) {
+                Some(url) => url.to_string(),
+                None => entry
+                    .links
+                    .iter()
+                    .find(|l| l.rel.as_deref() == Some("enclosure"))?
+                    .href
+                    .clone(),
+            };
+            let duration_secs = entry
+                .media
+                .iter()
+                .find_map(|m| {
+                    m.duration
+                        .or_else(|| m.content.iter().find_map(|c| c.duration))
+                })
+                .map(|d| d.as_secs());
+
+            Some(Episode {
+                title: entry
+                    .title
+                    .map(|t| t.content)
+                    .unwrap_or_else(|| entry.id.clone()),
+                id: entry.id,
+                url,
+                published: entry.published.or(entry.updated).map(|d| d.timestamp()),
+                duration_secs,
+                position_secs: 0,
+                finished: false,
+                cached_file: None,
+            })
+        })
+        .collect();
+
+    Ok(ParsedFeed {
+        title: feed
+            .title
+            .map(|t| t.content)
+            .unwrap_or_else(|| url.to_string()),
+        episodes,
+    })
+}
+
+/// Fetches the feeds of all subscriptions, merges new episodes and saves the podcasts at `path`.
+/// Returns the number of new episodes.
+pub(crate) async fn refresh(podcasts: &Mutex<Podcasts>, path: &Path) -> Result<usize> {
+    let feeds: Vec<(String, String)> = podcasts
+        .lock()
+        .await
+        .subscriptions
+        .iter()
+        .map(|s| (s.id.clone(), s.url.clone()))
+        .collect();
+
+    let mut new_episodes = 0;
+    for (id, url) in feeds {
+        match fetch_feed(&url).await {
+            Ok(feed) => new_episodes += podcasts.lock().await.merge(&id, feed),
+            Err(err) => warn!("Could not refresh podcast `{id}`: {err:#}"),
+        }
+    }
+
+    podcasts.lock().await.write(path)?;
+    Ok(new_episodes)
+}
+
+/// Downloads the episode into the `cache_dir` and returns the path of the downloaded file.
+pub(crate) async fn download_episode(
+    podcast_id: &str,
+    episode: &Episode,
+    cache_dir: &Path,
+) -> Result<PathBuf> {
+    let dir = cache_dir.join(podcast_id);
+    let url = episode.url.clone();
+    let episode_id = episode.id.clone();
+
+    task::spawn_blocking(move || -> Result<PathBuf> {
+        fs::create_dir_all(&dir)?;
+        let mut response = reqwest::blocking::get(&url)?.error_for_status()?;
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        let extension = episode_extension(&url, content_type);
+        let path = dir.join(cached_file_name(&episode_id, extension));
+        let mut file = File::create(&path)?;
+        if let Err(err) = io::copy(&mut response, &mut file) {
+            // do not leave a partially downloaded file behind
+            let _ = fs::remove_file(&path);
+            return Err(err.into());
+        }
+        Ok(path)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::{Arc, Mutex as StdMutex},
+        thread,
+    };
+
+    use super::*;
+
+    type Files = Arc<StdMutex<HashMap<String, (&'static str, Vec<u8>)>>>;
+
+    /// Serves the `files` by path over HTTP, they may be replaced while the server runs.
+    /// Returns the base URL.
+    fn serve(files: Files) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut path = String::new();
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(request) = line.strip_prefix("GET ") {
+                        path = request.split(' ').next().unwrap().to_string();
+                    }
+                }
+
+                let response = match files.lock().unwrap().get(&path) {
+                    Some((content_type, body)) => {
+                        let mut response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\n\
+                             Content-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .into_bytes();
+                        response.extend(body);
+                        response
+                    }
+                    None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+                              Connection: close\r\n\r\n"
+                        .to_vec(),
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+        url
+    }
+
+    /// Returns an RSS feed with an episode for each `(guid, enclosure URL)` and an entry
+    /// without an enclosure.
+    fn rss(episodes: &[(&str, &str)]) -> Vec<u8> {
+        let items: String = episodes
+            .iter()
+            .map(|(guid, url)| {
+                format!(
+                    "<item><guid>{guid}</guid><title>Title of {guid}</title>\
+                     <enclosure url=\"{url}\" type=\"audio/mpeg\" length=\"4\"/>\
+                     <itunes:duration>01:02:03</itunes:duration>\
+                     <pubDate>Mon, 02 Oct 2023 10:00:00 GMT</pubDate></item>"
+                )
+            })
+            .collect();
+        format!(
+            "<?xml version=\"1.0\"?><rss version=\"2.0\" \
+             xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">\
+             <channel><title>Test Show</title>{items}\
+             <item><guid>announcement</guid><title>No audio</title></item></channel></rss>"
+        )
+        .into_bytes()
+    }
+
+    #[tokio::test]
+    async fn subscribes_refreshes_and_downloads_feed() {
+        let files = Files::default();
+        let url = serve(files.clone());
+        let episode_1_url = format!("{url}/episode-1.mp3");
+        files.lock().unwrap().extend([
+            (
+                "/feed.xml".to_string(),
+                ("application/rss+xml", rss(&[("episode-1", &episode_1_url)])),
+            ),
+            (
+                "/episode-1.mp3".to_string(),
+                ("audio/mpeg", b"ID3\x04".to_vec()),
+            ),
+            (
+                "/atom.xml".to_string(),
+                (
+                    "application/atom+xml",
+                    format!(
+                        "<?xml version=\"1.0\"?><feed xmlns=\"http://www.w3.org/2005/Atom\">\
+                         <title>Atom Show</title><id>atom</id>\
+                         <updated>2023-10-02T10:00:00Z</updated>\
+                         <entry><id>atom-1</id><title>Atom 1</title>\
+                         <updated>2023-10-02T10:00:00Z</updated>\
+                         <link rel=\"enclosure\" type=\"audio/mpeg\" href=\"{episode_1_url}\"/>\
+                         </entry></feed>"
+                    )
+                    .into_bytes(),
+                ),
+            ),
+        ]);
+
+        let feed = fetch_feed(&format!("{url}/feed.xml")).await.unwrap();
+        assert_eq!(feed.title, "Test Show");
+        assert_eq!(feed.episodes.len(), 1);
+        let episode = &feed.episodes[0];
+        assert_eq!(episode.id, "episode-1");
+        assert_eq!(episode.title, "Title of episode-1");
+        assert_eq!(episode.url, episode_1_url);
+        assert_eq!(episode.duration_secs, Some(3723));
+        assert_eq!(episode.published, Some(1696240800));
+
+        let atom = fetch_feed(&format!("{url}/atom.xml")).await.unwrap();
+        assert_eq!(atom.title, "Atom Show");
+        assert_eq!(atom.episodes.len(), 1);
+        assert_eq!(atom.episodes[0].id, "atom-1");
+        assert_eq!(atom.episodes[0].url, episode_1_url);
+
+        let podcasts = Mutex::new(Podcasts::default());
+        let id = podcasts
+            .lock()
+            .await
+            .subscribe(format!("{url}/feed.xml"), feed)
+            .unwrap();
+        assert_eq!(id, "test-show");
+        assert!(podcasts
+            .lock()
+            .await
+            .record_position(&episode_1_url, Duration::from_secs(90)));
+
+        let episode_2_url = format!("{url}/episode-2.mp3");
+        files.lock().unwrap().insert(
+            "/feed.xml".to_string(),
+            (
+                "application/rss+xml",
+                rss(&[("episode-2", &episode_2_url), ("episode-1", &episode_1_url)]),
+            ),
+        );
+        let dir = std::env::temp_dir().join(format!("radio-podcast-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("podcasts.toml");
+        assert_eq!(refresh(&podcasts, &path).await.unwrap(), 1);
+        {
+            let podcasts = podcasts.lock().await;
+            assert_eq!(
+                podcasts.episode(&id, "episode-1").unwrap().position_secs,
+                90
+            );
+            assert_eq!(podcasts.episode(&id, "episode-2").unwrap().position_secs, 0);
+            let saved = read(&path).unwrap();
+            assert_eq!(saved.subscriptions()[0].episodes.len(), 2);
+        }
+
+        let episode = podcasts
+            .lock()
+            .await
+            .episode(&id, "episode-1")
+            .unwrap()
+            .clone();
+        let cache_dir = dir.join("cache");
+        let file = download_episode(&id, &episode, &cache_dir).await.unwrap();
+        assert!(file.starts_with(cache_dir.join(&id)));
+        assert_eq!(file.extension().unwrap(), "mp3");
+        assert_eq!(fs::read(&file).unwrap(), b"ID3\x04");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn podcasts(episode_url: &str) -> Podcasts {
+        Podcasts {
+            subscriptions: vec![Subscription {
+                id: "show".to_string(),
+                title: "Show".to_string(),
+                url: "http://localhost/feed.xml".to_string(),
+                episodes: vec![Episode {
+                    id: "episode-1".to_string(),
+                    title: "Episode 1".to_string(),
+                    url: episode_url.to_string(),
+                    published: None,
+                    duration_secs: Some(3600),
+                    position_secs: 0,
+                    finished: false,
+                    cached_file: None,
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn records_position_of_episode_downloaded_during_playback() {
+        let url = "http://localhost/episode-1.mp3";
+        let mut podcasts = podcasts(url);
+        let station = podcasts.episode("show", "episode-1").unwrap().station();
+
+        podcasts.set_cached_file("show", "episode-1", PathBuf::from("/tmp/episode-1.mp3"));
+
+        assert!(podcasts.record_position(&station.url, Duration::from_secs(90)));
+        assert_eq!(
+            podcasts.episode("show", "episode-1").unwrap().position_secs,
+            90
+        );
+    }
+
+    #[test]
+    fn cached_file_names_do_not_collide() {
+        assert_ne!(
+            cached_file_name("tag:example.com,2023:1", "mp3"),
+            cached_file_name("tag:example.com/2023/1", "mp3")
+        );
+    }
+
+    #[test]
+    fn episode_extension_uses_url_then_content_type() {
+        assert_eq!(episode_extension("http://localhost/a.M4A?x=1", None), "m4a");
+        assert_eq!(
+            episode_extension("http://localhost/play", Some("audio/ogg; codecs=opus")),
+            "ogg"
+        );
+        assert_eq!(episode_extension("http://localhost/play", None), "mp3");
+    }
+}
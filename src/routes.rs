@@ -1,18 +1,44 @@
-use std::path::PathBuf;
+use std::{
+    future::{ready, Ready},
+    ops::Deref,
+    path::PathBuf,
+    time::Duration,
+};
 
 use crate::{
-    audio::{self, Error as AudioError},
-    config::Station,
-    SETTINGS_PATH,
+    audio::{self, Error as AudioError, OutputDevice},
+    config::{Station, MAIN_ZONE},
+    dsp::{
+        channels::ChannelSettings,
+        compressor::{CompressorPreset, CompressorSettings},
+        equalizer::{self, EqualizerSettings},
+    },
+    library,
+    listen::ICY_METAINT,
+    output::{StreamSettings, VirtualHost},
+    playlist::{MediaRef, Playlist},
+    podcast,
+    settings::{MirrorOutput, Settings},
+    silence::SilenceIncident,
+    zapping,
+    zone::Zone,
+    PLAYLISTS_PATH, PODCASTS_PATH,
 };
 use actix_files::NamedFile;
 use actix_identity::Identity;
 use actix_web::{
-    get, post,
-    web::{Data, Json},
-    Error, HttpMessage, HttpRequest, HttpResponse, Responder,
+    delete, dev,
+    error::InternalError,
+    get,
+    http::header,
+    post, put,
+    web::{Data, Json, Path, Payload, Query, ServiceConfig},
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder,
 };
+use actix_ws::Message;
+use futures_util::stream;
 use serde::{Deserialize, Serialize};
+use tokio::sync::MutexGuard;
 
 use crate::State;
 
@@ -33,23 +59,147 @@ pub(crate) struct UrlPlayReq {
     url: String,
 }
 
+#[derive(Deserialize)]
+pub(crate) struct LibraryQuery {
+    artist: Option<String>,
+    album: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LibrarySearchQuery {
+    q: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LibraryPlayReq {
+    #[serde(rename = "trackId")]
+    track_id: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct LibraryScanRes {
+    tracks: usize,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct QueueMoveReq {
+    from: usize,
+    to: usize,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PlaylistReq {
+    items: Vec<MediaRef>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct EqualizerRes {
+    #[serde(flatten)]
+    settings: EqualizerSettings,
+    presets: Vec<&'static str>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CompressorRes {
+    #[serde(flatten)]
+    settings: CompressorSettings,
+    /// The preset which is currently applied, taking the schedule into account
+    active: CompressorPreset,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EqualizerPresetReq {
+    name: String,
+}
+
 #[derive(Deserialize)]
 pub(crate) struct VolumeReq {
     volume: u8,
 }
 
+#[derive(Deserialize)]
+pub(crate) struct SubscribeReq {
+    url: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SubscribeRes {
+    id: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EpisodeReq {
+    #[serde(rename = "podcastId")]
+    podcast_id: String,
+    #[serde(rename = "episodeId")]
+    episode_id: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RefreshRes {
+    #[serde(rename = "newEpisodes")]
+    new_episodes: usize,
+}
+
 #[derive(Serialize)]
 pub(crate) struct StatusRes {
     #[serde(rename = "stationId")]
     station_id: Option<String>,
+    #[serde(rename = "positionSecs")]
+    position_secs: Option<u64>,
     volume: u8,
+    #[serde(rename = "maxVolume")]
+    max_volume: u8,
+    muted: bool,
+    #[serde(rename = "lastSilence")]
+    last_silence: Option<SilenceIncident>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeviceReq {
+    /// The audio host of the device, the default host if omitted
+    host: Option<String>,
+    name: String,
 }
 
-#[derive(Deserialize, Serialize)]
-pub(crate) struct DeviceReqRes {
+#[derive(Deserialize)]
+pub(crate) struct IndexPath {
     index: usize,
 }
 
+#[derive(Deserialize)]
+pub(crate) struct NamePath {
+    name: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ZoneRes {
+    name: String,
+    #[serde(rename = "stationId")]
+    station_id: Option<String>,
+    device: OutputDevice,
+    volume: u8,
+    muted: bool,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MirrorReq {
+    volume: Option<u8>,
+    enabled: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DevicesQuery {
+    host: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DevicesRes {
+    host: &'static str,
+    hosts: Vec<audio::HostInfo>,
+    devices: Vec<audio::DeviceInfo>,
+}
+
 #[derive(Serialize)]
 struct GenericResponse {
     message: &'static str,
@@ -72,6 +222,87 @@ impl GenericResponse {
     }
 }
 
+/// The zone addressed by the `{zone}` segment of the path or the main zone if there is none.
+pub(crate) struct ZoneRef {
+    data: Data<State>,
+    index: usize,
+}
+
+impl Deref for ZoneRef {
+    type Target = Zone;
+
+    fn deref(&self) -> &Zone {
+        &self.data.zones[self.index]
+    }
+}
+
+impl FromRequest for ZoneRef {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+        let data = req
+            .app_data::<Data<State>>()
+            .expect("the state is registered")
+            .clone();
+        let name = req.match_info().get("zone").unwrap_or(MAIN_ZONE);
+
+        ready(match data.zones.iter().position(|z| z.name == name) {
+            Some(index) => Ok(Self { data, index }),
+            None => Err(InternalError::from_response(
+                "no such zone",
+                HttpResponse::NotFound().json(GenericResponse::err(
+                    "zone is unavailable",
+                    format!("the zone `{name}` does not exist"),
+                )),
+            )
+            .into()),
+        })
+    }
+}
+
+/// Registers the routes which control a single zone.
+/// They are available below `/api` for the main zone and below `/api/zones/{zone}` for every zone.
+pub(crate) fn zone_routes(cfg: &mut ServiceConfig) {
+    cfg.service(get_status)
+        .service(post_play)
+        .service(post_play_url)
+        .service(post_stop)
+        .service(post_next)
+        .service(post_previous)
+        .service(post_volume)
+        .service(post_mute)
+        .service(post_unmute)
+        .service(post_library_play)
+        .service(get_queue)
+        .service(post_queue)
+        .service(post_queue_next)
+        .service(post_queue_move)
+        .service(post_queue_skip)
+        .service(delete_queue_item)
+        .service(delete_queue)
+        .service(post_playlist_queue)
+        .service(post_podcast_play)
+        .service(get_equalizer)
+        .service(post_equalizer)
+        .service(post_equalizer_preset)
+        .service(get_compressor)
+        .service(post_compressor)
+        .service(get_channels)
+        .service(post_channels)
+        .service(get_output)
+        .service(post_output)
+        .service(get_levels)
+        .service(get_listen)
+        .service(get_devices)
+        .service(post_device)
+        .service(get_device)
+        .service(get_mirrors)
+        .service(post_mirror)
+        .service(put_mirror)
+        .service(delete_mirror);
+}
+
 #[get("/logout")]
 pub(crate) async fn logout(user: Identity) -> Result<HttpResponse, Error> {
     debug!("user `{}` is logging out", user.id().unwrap());
@@ -115,43 +346,122 @@ pub(crate) async fn post_login(
     }
 }
 
-#[get("/api/status")]
-pub(crate) async fn get_status(data: Data<State>) -> HttpResponse {
-    let mut player = data.player.lock().await;
-    let settings = data.settings.lock().await;
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Creates a station which plays an arbitrary URL.
+fn url_station(url: &str) -> Station {
+    Station {
+        id: "url".to_string(),
+        name: "URL".to_string(),
+        description: "A custom URL".to_string(),
+        url: url.to_string(),
+        image_file: PathBuf::from(""),
+        auto_restart: true,
+        auto_start: false,
+        gain_db: 0.0,
+        start_position: Duration::ZERO,
+    }
+}
+
+/// Resolves a media reference to a playable station.
+async fn resolve_media(data: &State, media: &MediaRef) -> Result<Station, String> {
+    match media {
+        MediaRef::Station { id } => data
+            .config
+            .stations
+            .iter()
+            .find(|s| &s.id == id)
+            .cloned()
+            .ok_or_else(|| format!("the station ID `{id}` does not exist")),
+        MediaRef::Track { id } => {
+            let Some(library) = &data.library else {
+                return Err("no library is configured".to_string());
+            };
+            let library = library.lock().await;
+            library
+                .track(id)
+                .map(|track| library.station(track))
+                .ok_or_else(|| format!("the track ID `{id}` does not exist"))
+        }
+        MediaRef::Url { url } if is_http_url(url) => Ok(url_station(url)),
+        MediaRef::Url { url } => Err(format!("the URL `{url}` is not an HTTP(S) URL")),
+        MediaRef::Episode {
+            podcast_id,
+            episode_id,
+        } => {
+            let Some(podcasts) = &data.podcasts else {
+                return Err("podcasts are not configured".to_string());
+            };
+            podcasts
+                .lock()
+                .await
+                .episode(podcast_id, episode_id)
+                .map(|episode| episode.station())
+                .ok_or_else(|| {
+                    format!("the episode `{episode_id}` of `{podcast_id}` does not exist")
+                })
+        }
+    }
+}
+
+#[get("/status")]
+pub(crate) async fn get_status(data: Data<State>, zone: ZoneRef) -> HttpResponse {
+    let mut player = zone.player.lock().await;
+    let settings = zone.settings.lock().await;
     let station_id = player.curr_station_id();
+    let position_secs = player.position().map(|p| p.as_secs());
+    let last_silence = player.last_silence().cloned();
 
     HttpResponse::Ok().json(StatusRes {
         station_id,
+        position_secs,
         volume: settings.volume_percent,
+        max_volume: data.config.max_volume_percent,
+        muted: settings.muted,
+        last_silence,
     })
 }
 
+#[get("/api/zones")]
+pub(crate) async fn get_zones(data: Data<State>, _user: Identity) -> HttpResponse {
+    let mut zones = Vec::with_capacity(data.zones.len());
+    for zone in &data.zones {
+        let station_id = zone.player.lock().await.curr_station_id();
+        let settings = zone.settings.lock().await;
+        zones.push(ZoneRes {
+            name: zone.name.clone(),
+            station_id,
+            device: settings.output_device.clone(),
+            volume: settings.volume_percent,
+            muted: settings.muted,
+        });
+    }
+    HttpResponse::Ok().json(zones)
+}
+
 #[get("/api/stations")]
 pub(crate) async fn get_stations(data: Data<State>) -> HttpResponse {
     HttpResponse::Ok().json(&data.config.stations)
 }
 
-#[post("/api/url")]
+#[post("/url")]
 pub(crate) async fn post_play_url(
-    data: Data<State>,
+    zone: ZoneRef,
     request: Json<UrlPlayReq>,
     _user: Identity,
 ) -> HttpResponse {
-    let mut player = data.player.lock().await;
-
-    match player
-        .play(Station {
-            id: "url".to_string(),
-            name: "URL".to_string(),
-            description: "A custom URL".to_string(),
-            url: request.url.to_string(),
-            image_file: PathBuf::from(""),
-            auto_restart: true,
-            auto_start: false,
-        })
-        .await
-    {
+    if !is_http_url(&request.url) {
+        return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+            "could not start playback",
+            "only HTTP(S) URLs are supported".to_string(),
+        ));
+    }
+
+    let mut player = zone.player.lock().await;
+
+    match player.play(url_station(&request.url)).await {
         Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("started playback")),
         Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
             "could not start playback",
@@ -160,13 +470,14 @@ pub(crate) async fn post_play_url(
     }
 }
 
-#[post("/api/play")]
+#[post("/play")]
 pub(crate) async fn post_play(
     data: Data<State>,
+    zone: ZoneRef,
     request: Json<PlayReq>,
     _user: Identity,
 ) -> HttpResponse {
-    let mut player = data.player.lock().await;
+    let mut player = zone.player.lock().await;
 
     match data
         .config
@@ -175,7 +486,10 @@ pub(crate) async fn post_play(
         .find(|s| s.id == request.station_id)
     {
         Some(station) => match player.play(station.clone()).await {
-            Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("started playback")),
+            Ok(_) => {
+                zapping::update_preconnections(&data.config, &mut player);
+                HttpResponse::Ok().json(GenericResponse::ok("started playback"))
+            }
             Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
                 "could not start playback",
                 err.to_string(),
@@ -188,9 +502,41 @@ pub(crate) async fn post_play(
     }
 }
 
-#[post("/api/stop")]
-pub(crate) async fn post_stop(data: Data<State>, _user: Identity) -> impl Responder {
-    let mut player = data.player.lock().await;
+/// Switches to the station which is `offset` positions away from the current one.
+async fn zap(data: &State, zone: &Zone, offset: isize) -> HttpResponse {
+    let mut player = zone.player.lock().await;
+    let current = player.curr_station_id();
+    let station = zapping::neighbour(&data.config, current.as_deref(), offset);
+
+    match player.play(station).await {
+        Ok(_) => {
+            zapping::update_preconnections(&data.config, &mut player);
+            HttpResponse::Ok().json(GenericResponse::ok("switched station"))
+        }
+        Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+            "could not switch station",
+            err.to_string(),
+        )),
+    }
+}
+
+#[post("/next")]
+pub(crate) async fn post_next(data: Data<State>, zone: ZoneRef, _user: Identity) -> HttpResponse {
+    zap(&data, &zone, 1).await
+}
+
+#[post("/previous")]
+pub(crate) async fn post_previous(
+    data: Data<State>,
+    zone: ZoneRef,
+    _user: Identity,
+) -> HttpResponse {
+    zap(&data, &zone, -1).await
+}
+
+#[post("/stop")]
+pub(crate) async fn post_stop(zone: ZoneRef, _user: Identity) -> impl Responder {
+    let mut player = zone.player.lock().await;
 
     match player.stop(true) {
         Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("stopped playing")),
@@ -205,19 +551,28 @@ pub(crate) async fn post_stop(data: Data<State>, _user: Identity) -> impl Respon
     }
 }
 
-#[post("/api/volume")]
+#[post("/volume")]
 pub(crate) async fn post_volume(
     data: Data<State>,
+    zone: ZoneRef,
     request: Json<VolumeReq>,
     _user: Identity,
 ) -> impl Responder {
-    let mut player = data.player.lock().await;
+    let max_volume = data.config.max_volume_percent;
+    if request.volume > max_volume {
+        return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+            "could not set volume",
+            format!("volume must be <= {max_volume}%"),
+        ));
+    }
+
+    let mut player = zone.player.lock().await;
     player.set_volume(request.volume);
 
-    let settings = &mut data.settings.lock().await;
+    let settings = &mut zone.settings.lock().await;
     settings.volume_percent = request.volume;
 
-    match settings.write(&PathBuf::from(SETTINGS_PATH)) {
+    match settings.write(&zone.settings_path) {
         Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("successfully set volume")),
         Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
             "could not set volume",
@@ -226,62 +581,1083 @@ pub(crate) async fn post_volume(
     }
 }
 
-#[get("/api/devices")]
-pub(crate) async fn get_devices(_user: Identity) -> impl Responder {
-    match audio::list_host_devices() {
-        Ok(devices) => HttpResponse::Ok().json(devices),
+/// Mutes or unmutes the output and persists the state, the volume is left unchanged.
+async fn set_muted(zone: &Zone, muted: bool) -> HttpResponse {
+    zone.player.lock().await.set_muted(muted);
+
+    let settings = &mut zone.settings.lock().await;
+    settings.muted = muted;
+
+    let (ok, err) = match muted {
+        true => ("successfully muted", "could not mute"),
+        false => ("successfully unmuted", "could not unmute"),
+    };
+    match settings.write(&zone.settings_path) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok(ok)),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            err,
+            "could not write to settings file".to_string(),
+        )),
+    }
+}
+
+#[post("/mute")]
+pub(crate) async fn post_mute(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    set_muted(&zone, true).await
+}
+
+#[post("/unmute")]
+pub(crate) async fn post_unmute(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    set_muted(&zone, false).await
+}
+
+fn library_not_configured() -> HttpResponse {
+    HttpResponse::NotFound().json(GenericResponse::err(
+        "library is unavailable",
+        "no library is configured".to_string(),
+    ))
+}
+
+#[get("/api/library")]
+pub(crate) async fn get_library(
+    data: Data<State>,
+    query: Query<LibraryQuery>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(library) = &data.library else {
+        return library_not_configured();
+    };
+    let library = library.lock().await;
+
+    let tracks: Vec<_> = library
+        .tracks()
+        .iter()
+        .filter(|t| query.artist.is_none() || t.artist == query.artist)
+        .filter(|t| query.album.is_none() || t.album == query.album)
+        .collect();
+
+    HttpResponse::Ok().json(tracks)
+}
+
+#[get("/api/library/search")]
+pub(crate) async fn get_library_search(
+    data: Data<State>,
+    query: Query<LibrarySearchQuery>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(library) = &data.library else {
+        return library_not_configured();
+    };
+    HttpResponse::Ok().json(library.lock().await.search(&query.q))
+}
+
+#[post("/api/library/scan")]
+pub(crate) async fn post_library_scan(data: Data<State>, _user: Identity) -> HttpResponse {
+    let Some(library) = &data.library else {
+        return library_not_configured();
+    };
+    match library::rescan(library).await {
+        Ok(tracks) => HttpResponse::Ok().json(LibraryScanRes { tracks }),
+        Err(err) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not scan library",
+            err.to_string(),
+        )),
+    }
+}
+
+#[post("/library/play")]
+pub(crate) async fn post_library_play(
+    data: Data<State>,
+    zone: ZoneRef,
+    request: Json<LibraryPlayReq>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(library) = &data.library else {
+        return library_not_configured();
+    };
+
+    let station = {
+        let library = library.lock().await;
+        let Some(track) = library.track(&request.track_id) else {
+            return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+                "could not start playback",
+                "this track ID does not exist".to_string(),
+            ));
+        };
+        library.station(track)
+    };
+
+    let mut player = zone.player.lock().await;
+    match player.play(station).await {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("started playback")),
         Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
-            "could not list devices",
+            "could not start playback",
             err.to_string(),
         )),
     }
 }
 
-#[get("/api/device")]
-pub(crate) async fn get_device(data: Data<State>, _user: Identity) -> impl Responder {
-    let settings = data.settings.lock().await;
-    HttpResponse::Ok().json(DeviceReqRes {
-        index: settings.alsa_device_index,
+#[get("/queue")]
+pub(crate) async fn get_queue(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    HttpResponse::Ok().json(zone.player.lock().await.queue())
+}
+
+/// Appends an item to the queue.
+/// If the player is currently idle, playback of the queue is started.
+#[post("/queue")]
+pub(crate) async fn post_queue(
+    data: Data<State>,
+    zone: ZoneRef,
+    request: Json<MediaRef>,
+    _user: Identity,
+) -> HttpResponse {
+    let station = match resolve_media(&data, &request).await {
+        Ok(station) => station,
+        Err(err) => {
+            return HttpResponse::UnprocessableEntity()
+                .json(GenericResponse::err("could not add to queue", err))
+        }
+    };
+
+    let mut player = zone.player.lock().await;
+    player.enqueue(station);
+
+    if player.curr_station_id().is_some() {
+        return HttpResponse::Ok().json(GenericResponse::ok("added to queue"));
+    }
+    match player.skip().await {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("started playback of queue")),
+        Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+            "could not start playback of queue",
+            err.to_string(),
+        )),
+    }
+}
+
+#[post("/queue/next")]
+pub(crate) async fn post_queue_next(
+    data: Data<State>,
+    zone: ZoneRef,
+    request: Json<MediaRef>,
+    _user: Identity,
+) -> HttpResponse {
+    match resolve_media(&data, &request).await {
+        Ok(station) => {
+            zone.player.lock().await.enqueue_next(station);
+            HttpResponse::Ok().json(GenericResponse::ok("added to queue"))
+        }
+        Err(err) => HttpResponse::UnprocessableEntity()
+            .json(GenericResponse::err("could not add to queue", err)),
+    }
+}
+
+#[delete("/queue/{index}")]
+pub(crate) async fn delete_queue_item(
+    zone: ZoneRef,
+    path: Path<IndexPath>,
+    _user: Identity,
+) -> HttpResponse {
+    match zone.player.lock().await.remove_from_queue(path.index) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("removed from queue")),
+        Err(err) => HttpResponse::BadRequest().json(GenericResponse::err(
+            "could not remove from queue",
+            err.to_string(),
+        )),
+    }
+}
+
+#[post("/queue/move")]
+pub(crate) async fn post_queue_move(
+    zone: ZoneRef,
+    request: Json<QueueMoveReq>,
+    _user: Identity,
+) -> HttpResponse {
+    match zone
+        .player
+        .lock()
+        .await
+        .move_in_queue(request.from, request.to)
+    {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("moved queue item")),
+        Err(err) => HttpResponse::BadRequest().json(GenericResponse::err(
+            "could not move queue item",
+            err.to_string(),
+        )),
+    }
+}
+
+#[delete("/queue")]
+pub(crate) async fn delete_queue(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    zone.player.lock().await.clear_queue();
+    HttpResponse::Ok().json(GenericResponse::ok("cleared queue"))
+}
+
+#[post("/queue/skip")]
+pub(crate) async fn post_queue_skip(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    let mut player = zone.player.lock().await;
+    match player.skip().await {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("skipped to next queue item")),
+        Err(err @ AudioError::NotPlaying) => {
+            HttpResponse::BadRequest().json(GenericResponse::err("could not skip", err.to_string()))
+        }
+        Err(err) => HttpResponse::ServiceUnavailable()
+            .json(GenericResponse::err("could not skip", err.to_string())),
+    }
+}
+
+#[get("/api/playlists")]
+pub(crate) async fn get_playlists(data: Data<State>, _user: Identity) -> HttpResponse {
+    HttpResponse::Ok().json(data.playlists.lock().await.all())
+}
+
+#[put("/api/playlists/{name}")]
+pub(crate) async fn put_playlist(
+    data: Data<State>,
+    name: Path<String>,
+    request: Json<PlaylistReq>,
+    _user: Identity,
+) -> HttpResponse {
+    let mut playlists = data.playlists.lock().await;
+    playlists.save(Playlist {
+        name: name.into_inner(),
+        items: request.into_inner().items,
+    });
+
+    match playlists.write(&PathBuf::from(PLAYLISTS_PATH)) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("successfully saved playlist")),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not save playlist",
+            "could not write to playlists file".to_string(),
+        )),
+    }
+}
+
+#[delete("/api/playlists/{name}")]
+pub(crate) async fn delete_playlist(
+    data: Data<State>,
+    name: Path<String>,
+    _user: Identity,
+) -> HttpResponse {
+    let mut playlists = data.playlists.lock().await;
+    if !playlists.remove(&name) {
+        return HttpResponse::NotFound().json(GenericResponse::err(
+            "could not delete playlist",
+            "this playlist does not exist".to_string(),
+        ));
+    }
+
+    match playlists.write(&PathBuf::from(PLAYLISTS_PATH)) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("successfully deleted playlist")),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not delete playlist",
+            "could not write to playlists file".to_string(),
+        )),
+    }
+}
+
+/// Appends all items of the playlist to the queue.
+/// If the player is currently idle, playback of the queue is started.
+#[post("/playlists/{name}/queue")]
+pub(crate) async fn post_playlist_queue(
+    data: Data<State>,
+    zone: ZoneRef,
+    path: Path<NamePath>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(playlist) = data.playlists.lock().await.get(&path.name).cloned() else {
+        return HttpResponse::NotFound().json(GenericResponse::err(
+            "could not queue playlist",
+            "this playlist does not exist".to_string(),
+        ));
+    };
+
+    let mut stations = Vec::with_capacity(playlist.items.len());
+    for item in &playlist.items {
+        match resolve_media(&data, item).await {
+            Ok(station) => stations.push(station),
+            Err(err) => warn!("Skipping item of playlist `{}`: {err}", playlist.name),
+        }
+    }
+
+    let mut player = zone.player.lock().await;
+    for station in stations {
+        player.enqueue(station);
+    }
+
+    if player.curr_station_id().is_some() {
+        return HttpResponse::Ok().json(GenericResponse::ok("added playlist to queue"));
+    }
+    match player.skip().await {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("started playback of playlist")),
+        Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+            "could not start playback of playlist",
+            err.to_string(),
+        )),
+    }
+}
+
+fn podcasts_not_configured() -> HttpResponse {
+    HttpResponse::NotFound().json(GenericResponse::err(
+        "podcasts are unavailable",
+        "podcasts are not configured".to_string(),
+    ))
+}
+
+fn no_such_episode(message: &'static str) -> HttpResponse {
+    HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+        message,
+        "this episode does not exist".to_string(),
+    ))
+}
+
+#[get("/api/podcasts")]
+pub(crate) async fn get_podcasts(data: Data<State>, _user: Identity) -> HttpResponse {
+    let Some(podcasts) = &data.podcasts else {
+        return podcasts_not_configured();
+    };
+    HttpResponse::Ok().json(podcasts.lock().await.subscriptions())
+}
+
+#[post("/api/podcasts")]
+pub(crate) async fn post_podcast(
+    data: Data<State>,
+    request: Json<SubscribeReq>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(podcasts) = &data.podcasts else {
+        return podcasts_not_configured();
+    };
+    if !is_http_url(&request.url) {
+        return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+            "could not subscribe to podcast",
+            "only HTTP(S) URLs are supported".to_string(),
+        ));
+    }
+
+    let feed = match podcast::fetch_feed(&request.url).await {
+        Ok(feed) => feed,
+        Err(err) => {
+            return HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+                "could not subscribe to podcast",
+                format!("{err:#}"),
+            ))
+        }
+    };
+
+    let mut podcasts = podcasts.lock().await;
+    let id = match podcasts.subscribe(request.url.clone(), feed) {
+        Ok(id) => id,
+        Err(err) => {
+            return HttpResponse::BadRequest().json(GenericResponse::err(
+                "could not subscribe to podcast",
+                err.to_string(),
+            ))
+        }
+    };
+
+    match podcasts.write(&PathBuf::from(PODCASTS_PATH)) {
+        Ok(_) => HttpResponse::Ok().json(SubscribeRes { id }),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not subscribe to podcast",
+            "could not write to podcasts file".to_string(),
+        )),
+    }
+}
+
+#[delete("/api/podcasts/{id}")]
+pub(crate) async fn delete_podcast(
+    data: Data<State>,
+    id: Path<String>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(podcasts) = &data.podcasts else {
+        return podcasts_not_configured();
+    };
+
+    let mut podcasts = podcasts.lock().await;
+    if !podcasts.unsubscribe(&id) {
+        return HttpResponse::NotFound().json(GenericResponse::err(
+            "could not unsubscribe from podcast",
+            "this podcast does not exist".to_string(),
+        ));
+    }
+
+    match podcasts.write(&PathBuf::from(PODCASTS_PATH)) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("successfully unsubscribed")),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not unsubscribe from podcast",
+            "could not write to podcasts file".to_string(),
+        )),
+    }
+}
+
+#[post("/api/podcasts/refresh")]
+pub(crate) async fn post_podcasts_refresh(data: Data<State>, _user: Identity) -> HttpResponse {
+    let Some(podcasts) = &data.podcasts else {
+        return podcasts_not_configured();
+    };
+    match podcast::refresh(podcasts, &PathBuf::from(PODCASTS_PATH)).await {
+        Ok(new_episodes) => HttpResponse::Ok().json(RefreshRes { new_episodes }),
+        Err(err) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not refresh podcasts",
+            err.to_string(),
+        )),
+    }
+}
+
+/// Plays the episode, resuming at the last known position.
+#[post("/podcasts/play")]
+pub(crate) async fn post_podcast_play(
+    data: Data<State>,
+    zone: ZoneRef,
+    request: Json<EpisodeReq>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(podcasts) = &data.podcasts else {
+        return podcasts_not_configured();
+    };
+    let Some(station) = podcasts
+        .lock()
+        .await
+        .episode(&request.podcast_id, &request.episode_id)
+        .map(|episode| episode.station())
+    else {
+        return no_such_episode("could not start playback");
+    };
+
+    let mut player = zone.player.lock().await;
+    match player.play(station).await {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("started playback")),
+        Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+            "could not start playback",
+            err.to_string(),
+        )),
+    }
+}
+
+/// Downloads the episode so that it can be played from the local cache.
+#[post("/api/podcasts/download")]
+pub(crate) async fn post_podcast_download(
+    data: Data<State>,
+    request: Json<EpisodeReq>,
+    _user: Identity,
+) -> HttpResponse {
+    let (Some(podcasts), Some(config)) = (&data.podcasts, &data.config.podcasts) else {
+        return podcasts_not_configured();
+    };
+    let Some(episode) = podcasts
+        .lock()
+        .await
+        .episode(&request.podcast_id, &request.episode_id)
+        .cloned()
+    else {
+        return no_such_episode("could not download episode");
+    };
+
+    let path =
+        match podcast::download_episode(&request.podcast_id, &episode, &config.cache_path).await {
+            Ok(path) => path,
+            Err(err) => {
+                return HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+                    "could not download episode",
+                    format!("{err:#}"),
+                ))
+            }
+        };
+
+    let mut podcasts = podcasts.lock().await;
+    podcasts.set_cached_file(&request.podcast_id, &request.episode_id, path);
+
+    match podcasts.write(&PathBuf::from(PODCASTS_PATH)) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("successfully downloaded episode")),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not download episode",
+            "could not write to podcasts file".to_string(),
+        )),
+    }
+}
+
+/// Deletes the downloaded file of the episode.
+#[post("/api/podcasts/discard")]
+pub(crate) async fn post_podcast_discard(
+    data: Data<State>,
+    request: Json<EpisodeReq>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(podcasts) = &data.podcasts else {
+        return podcasts_not_configured();
+    };
+
+    let mut podcasts = podcasts.lock().await;
+    if !podcasts.remove_cached(&request.podcast_id, &request.episode_id) {
+        return HttpResponse::BadRequest().json(GenericResponse::err(
+            "could not discard download",
+            "this episode is not downloaded".to_string(),
+        ));
+    }
+
+    match podcasts.write(&PathBuf::from(PODCASTS_PATH)) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("successfully discarded download")),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not discard download",
+            "could not write to podcasts file".to_string(),
+        )),
+    }
+}
+
+#[get("/equalizer")]
+pub(crate) async fn get_equalizer(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    let settings = zone.settings.lock().await;
+    HttpResponse::Ok().json(EqualizerRes {
+        settings: settings.equalizer.clone(),
+        presets: equalizer::preset_names(),
     })
 }
 
-#[post("/api/device")]
+/// Applies the equalizer settings to the running stream and persists them.
+async fn set_equalizer(zone: &Zone, equalizer: EqualizerSettings) -> HttpResponse {
+    zone.player
+        .lock()
+        .await
+        .dsp()
+        .equalizer()
+        .set(equalizer.clone());
+
+    let settings = &mut zone.settings.lock().await;
+    settings.equalizer = equalizer;
+
+    match settings.write(&zone.settings_path) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("successfully set equalizer")),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not set equalizer",
+            "could not write to settings file".to_string(),
+        )),
+    }
+}
+
+#[post("/equalizer")]
+pub(crate) async fn post_equalizer(
+    zone: ZoneRef,
+    request: Json<EqualizerSettings>,
+    _user: Identity,
+) -> HttpResponse {
+    if let Err(err) = request.validate() {
+        return HttpResponse::UnprocessableEntity()
+            .json(GenericResponse::err("could not set equalizer", err));
+    }
+    set_equalizer(&zone, request.into_inner()).await
+}
+
+#[post("/equalizer/preset")]
+pub(crate) async fn post_equalizer_preset(
+    zone: ZoneRef,
+    request: Json<EqualizerPresetReq>,
+    _user: Identity,
+) -> HttpResponse {
+    let Some(bands) = equalizer::preset(&request.name) else {
+        return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+            "could not set equalizer",
+            "this preset does not exist".to_string(),
+        ));
+    };
+    set_equalizer(
+        &zone,
+        EqualizerSettings {
+            enabled: true,
+            preset: Some(request.name.clone()),
+            bands,
+        },
+    )
+    .await
+}
+
+#[get("/compressor")]
+pub(crate) async fn get_compressor(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    let settings = zone.settings.lock().await.compressor.clone();
+    let active = zone.player.lock().await.dsp().compressor().preset();
+    HttpResponse::Ok().json(CompressorRes { settings, active })
+}
+
+#[post("/compressor")]
+pub(crate) async fn post_compressor(
+    zone: ZoneRef,
+    request: Json<CompressorSettings>,
+    _user: Identity,
+) -> HttpResponse {
+    if let Err(err) = request.validate() {
+        return HttpResponse::UnprocessableEntity()
+            .json(GenericResponse::err("could not set compressor", err));
+    }
+    let compressor = request.into_inner();
+
+    zone.player
+        .lock()
+        .await
+        .dsp()
+        .compressor()
+        .set(compressor.active_preset(chrono::Local::now().time()));
+
+    let settings = &mut zone.settings.lock().await;
+    settings.compressor = compressor;
+
+    match settings.write(&zone.settings_path) {
+        Ok(_) => HttpResponse::Ok().json(GenericResponse::ok("successfully set compressor")),
+        Err(_) => HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not set compressor",
+            "could not write to settings file".to_string(),
+        )),
+    }
+}
+
+#[get("/channels")]
+pub(crate) async fn get_channels(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    let settings = zone.settings.lock().await;
+    HttpResponse::Ok().json(&settings.channels)
+}
+
+#[post("/channels")]
+pub(crate) async fn post_channels(
+    data: Data<State>,
+    zone: ZoneRef,
+    request: Json<ChannelSettings>,
+    _user: Identity,
+) -> HttpResponse {
+    if let Err(err) = request.validate() {
+        return HttpResponse::UnprocessableEntity()
+            .json(GenericResponse::err("could not set channels", err));
+    }
+
+    let channels = request.into_inner();
+    {
+        let settings = &mut zone.settings.lock().await;
+        settings.channels = channels.clone();
+
+        if settings.write(&zone.settings_path).is_err() {
+            return HttpResponse::InternalServerError().json(GenericResponse::err(
+                "could not set channels",
+                "could not write to settings file".to_string(),
+            ));
+        }
+    }
+
+    // the settings are released first, since other handlers lock the player before the settings
+    let player = &mut zone.player.lock().await;
+    match player.set_channels(channels).await {
+        Ok(_) => {
+            zapping::update_preconnections(&data.config, player);
+            HttpResponse::Ok().json(GenericResponse::ok("successfully set channels"))
+        }
+        Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+            "could not restart player",
+            err.to_string(),
+        )),
+    }
+}
+
+#[get("/output")]
+pub(crate) async fn get_output(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    let settings = zone.settings.lock().await;
+    HttpResponse::Ok().json(&settings.stream)
+}
+
+#[post("/output")]
+pub(crate) async fn post_output(
+    data: Data<State>,
+    zone: ZoneRef,
+    request: Json<StreamSettings>,
+    _user: Identity,
+) -> HttpResponse {
+    if let Err(err) = request.validate() {
+        return HttpResponse::UnprocessableEntity()
+            .json(GenericResponse::err("could not set output stream", err));
+    }
+
+    let stream = request.into_inner();
+    {
+        let settings = &mut zone.settings.lock().await;
+        settings.stream = stream.clone();
+
+        if settings.write(&zone.settings_path).is_err() {
+            return HttpResponse::InternalServerError().json(GenericResponse::err(
+                "could not set output stream",
+                "could not write to settings file".to_string(),
+            ));
+        }
+    }
+
+    // the settings are released first, since other handlers lock the player before the settings
+    let player = &mut zone.player.lock().await;
+    match player.set_stream_settings(stream).await {
+        Ok(_) => {
+            zapping::update_preconnections(&data.config, player);
+            HttpResponse::Ok().json(GenericResponse::ok("successfully set output stream"))
+        }
+        Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+            "could not restart player",
+            err.to_string(),
+        )),
+    }
+}
+
+/// Streams the levels of the played audio to the client while the WebSocket is open.
+#[get("/levels")]
+pub(crate) async fn get_levels(
+    zone: ZoneRef,
+    req: HttpRequest,
+    body: Payload,
+    _user: Identity,
+) -> Result<HttpResponse, Error> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let mut levels = zone.player.lock().await.meter().subscribe();
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                changed = levels.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                    let json = serde_json::to_string(&*levels.borrow_and_update()).unwrap();
+                    if session.text(json).await.is_err() {
+                        break;
+                    }
+                }
+                message = messages.recv() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        trace!("Level meter client disconnected");
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+/// Streams the played audio to the client as Ogg/Opus.
+/// Clients which send `Icy-MetaData: 1` receive the station name as ICY metadata.
+#[get("/listen")]
+pub(crate) async fn get_listen(zone: ZoneRef, req: HttpRequest, _user: Identity) -> HttpResponse {
+    let Some(listen) = zone.player.lock().await.listen().cloned() else {
+        return HttpResponse::NotFound().json(GenericResponse::err(
+            "listen stream is unavailable",
+            "listening is not configured".to_string(),
+        ));
+    };
+    let icy = req
+        .headers()
+        .get("Icy-MetaData")
+        .is_some_and(|value| value == "1");
+    let listener = listen.subscribe(icy);
+
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("audio/ogg")
+        .insert_header((header::CACHE_CONTROL, "no-cache, no-store"));
+    if icy {
+        response
+            .insert_header(("icy-metaint", ICY_METAINT.to_string()))
+            .insert_header(("icy-name", format!("radio ({})", zone.name)));
+    }
+    response.streaming(stream::unfold(listener, |mut listener| async move {
+        let data = listener.next().await?;
+        Some((Ok::<_, Error>(data), listener))
+    }))
+}
+
+#[get("/devices")]
+pub(crate) async fn get_devices(
+    data: Data<State>,
+    zone: ZoneRef,
+    query: Query<DevicesQuery>,
+    _user: Identity,
+) -> impl Responder {
+    // the devices of the selected host are listed by default
+    let host = match &query.host {
+        Some(host) => Some(host.clone()),
+        None => zone.settings.lock().await.output_device.host.clone(),
+    };
+    match audio::host_name(host.as_deref())
+        .and_then(|name| Ok((name, audio::list_host_devices(Some(name))?)))
+    {
+        Ok((host, devices)) => HttpResponse::Ok().json(DevicesRes {
+            host,
+            hosts: audio::list_hosts()
+                .into_iter()
+                .map(|mut host| {
+                    // the pipe output can only be used once its format is configured
+                    if host.name == VirtualHost::Pipe.name() {
+                        host.available = data.config.pipe.is_some();
+                    }
+                    host
+                })
+                .collect(),
+            devices,
+        }),
+        Err(err) => host_error_response("could not list devices", err),
+    }
+}
+
+/// Unknown hosts are rejected as invalid requests, other errors mean that the host is unusable.
+fn host_error_response(message: &'static str, err: AudioError) -> HttpResponse {
+    match err {
+        AudioError::NoSuchHost(_) => {
+            HttpResponse::UnprocessableEntity().json(GenericResponse::err(message, err.to_string()))
+        }
+        _ => {
+            HttpResponse::ServiceUnavailable().json(GenericResponse::err(message, err.to_string()))
+        }
+    }
+}
+
+#[get("/device")]
+pub(crate) async fn get_device(zone: ZoneRef, _user: Identity) -> impl Responder {
+    let settings = zone.settings.lock().await;
+    HttpResponse::Ok().json(&settings.output_device)
+}
+
+/// Looks up the device of the request, the error response uses the `message`.
+fn find_requested_device(
+    data: &State,
+    request: &DeviceReq,
+    message: &'static str,
+) -> Result<OutputDevice, Box<HttpResponse>> {
+    let host = match request
+        .host
+        .as_deref()
+        .map(|h| audio::host_name(Some(h)))
+        .transpose()
+    {
+        Ok(host) => host,
+        Err(err) => return Err(Box::new(host_error_response(message, err))),
+    };
+    if host == Some(VirtualHost::Pipe.name()) && data.config.pipe.is_none() {
+        return Err(Box::new(HttpResponse::UnprocessableEntity().json(
+            GenericResponse::err(message, AudioError::NoPipeOutput.to_string()),
+        )));
+    }
+    match audio::list_host_devices(host) {
+        Ok(devices) => match devices.into_iter().find(|d| d.name == request.name) {
+            Some(device) => Ok(OutputDevice {
+                host: host.map(|h| h.to_string()),
+                name: device.name,
+                index: device.index,
+            }),
+            None => Err(Box::new(HttpResponse::BadRequest().json(
+                GenericResponse::err(message, "this device does not exist".to_string()),
+            ))),
+        },
+        Err(err) => Err(Box::new(host_error_response("could not list devices", err))),
+    }
+}
+
+/// Returns the name of another zone which already plays on the `device`.
+async fn zone_using_device(data: &State, zone: &Zone, device: &OutputDevice) -> Option<String> {
+    if VirtualHost::is_shareable(device) {
+        return None;
+    }
+    for other in data.zones.iter().filter(|z| z.name != zone.name) {
+        if other.settings.lock().await.uses_device(device) {
+            return Some(other.name.clone());
+        }
+    }
+    None
+}
+
+#[post("/device")]
 pub(crate) async fn post_device(
     data: Data<State>,
-    request: Json<DeviceReqRes>,
+    zone: ZoneRef,
+    request: Json<DeviceReq>,
     _user: Identity,
 ) -> impl Responder {
-    match audio::list_host_devices() {
-        Ok(devices) => {
-            if devices.get(request.index).is_none() {
-                return HttpResponse::BadRequest().json(GenericResponse::err(
-                    "could not change output device",
-                    "this device does not exist".to_string(),
-                ));
-            }
+    let device = match find_requested_device(&data, &request, "could not change output device") {
+        Ok(device) => device,
+        Err(response) => return *response,
+    };
+    if let Some(other) = zone_using_device(&data, &zone, &device).await {
+        return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+            "could not change output device",
+            format!("this device is already used by zone `{other}`"),
+        ));
+    }
+
+    let profile = {
+        let settings = &mut zone.settings.lock().await;
+        let profile = settings.switch_device(device.clone(), data.config.max_volume_percent);
+
+        if settings.write(&zone.settings_path).is_err() {
+            return HttpResponse::InternalServerError().json(GenericResponse::err(
+                "could not change output device",
+                "could not write to settings file".to_string(),
+            ));
+        }
+        profile
+    };
+
+    // the settings are released first, since other handlers lock the player before the settings
+    let player = &mut zone.player.lock().await;
+    match player.set_output_device(device, &profile).await {
+        Ok(_) => {
+            zapping::update_preconnections(&data.config, player);
+            HttpResponse::Ok().json(GenericResponse::ok("successfully changed output device"))
+        }
+        Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
+            "could not restart player",
+            err.to_string(),
+        )),
+    }
+}
+
+#[get("/mirrors")]
+pub(crate) async fn get_mirrors(zone: ZoneRef, _user: Identity) -> HttpResponse {
+    let settings = zone.settings.lock().await;
+    HttpResponse::Ok().json(&settings.mirrors)
+}
+
+#[post("/mirrors")]
+pub(crate) async fn post_mirror(
+    data: Data<State>,
+    zone: ZoneRef,
+    request: Json<DeviceReq>,
+    _user: Identity,
+) -> HttpResponse {
+    let device = match find_requested_device(&data, &request, "could not add mirror output") {
+        Ok(device) => device,
+        Err(response) => return *response,
+    };
+    if let Some(other) = zone_using_device(&data, &zone, &device).await {
+        return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+            "could not add mirror output",
+            format!("this device is already used by zone `{other}`"),
+        ));
+    }
+
+    let mut settings = zone.settings.lock().await;
+    if settings.uses_device(&device) {
+        return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+            "could not add mirror output",
+            "this device is already in use".to_string(),
+        ));
+    }
+    settings.mirrors.push(MirrorOutput {
+        device,
+        volume: 100,
+        enabled: true,
+    });
+
+    apply_mirrors(&data, &zone, settings, "successfully added mirror output").await
+}
+
+#[put("/mirrors/{index}")]
+pub(crate) async fn put_mirror(
+    data: Data<State>,
+    zone: ZoneRef,
+    path: Path<IndexPath>,
+    request: Json<MirrorReq>,
+    _user: Identity,
+) -> HttpResponse {
+    if request.volume.is_some_and(|v| v > 100) {
+        return HttpResponse::UnprocessableEntity().json(GenericResponse::err(
+            "could not update mirror output",
+            "volume must be <= 100%".to_string(),
+        ));
+    }
 
-            let settings = &mut data.settings.lock().await;
-            settings.alsa_device_index = request.index;
+    let mut settings = zone.settings.lock().await;
+    let Some(mirror) = settings.mirrors.get_mut(path.index) else {
+        return HttpResponse::BadRequest().json(GenericResponse::err(
+            "could not update mirror output",
+            AudioError::NoSuchMirror(path.index).to_string(),
+        ));
+    };
 
-            if settings.write(&PathBuf::from(SETTINGS_PATH)).is_err() {
+    if let Some(volume) = request.volume {
+        mirror.volume = volume;
+    }
+    // volume changes are applied while playing, enabling or disabling reopens the outputs
+    match request.enabled {
+        Some(enabled) if enabled != mirror.enabled => {
+            mirror.enabled = enabled;
+            apply_mirrors(&data, &zone, settings, "successfully updated mirror output").await
+        }
+        _ => {
+            let volume = mirror.volume;
+            if settings.write(&zone.settings_path).is_err() {
                 return HttpResponse::InternalServerError().json(GenericResponse::err(
-                    "could not change output device",
+                    "could not update mirror output",
                     "could not write to settings file".to_string(),
                 ));
             }
-
-            let player = &mut data.player.lock().await;
-            match player.set_output_device(request.index).await {
+            drop(settings);
+            match zone
+                .player
+                .lock()
+                .await
+                .set_mirror_volume(path.index, volume)
+            {
                 Ok(_) => HttpResponse::Ok()
-                    .json(GenericResponse::ok("successfully changed output device")),
-                Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
-                    "could not restart player",
+                    .json(GenericResponse::ok("successfully updated mirror output")),
+                Err(err) => HttpResponse::BadRequest().json(GenericResponse::err(
+                    "could not update mirror output",
                     err.to_string(),
                 )),
             }
         }
+    }
+}
+
+#[delete("/mirrors/{index}")]
+pub(crate) async fn delete_mirror(
+    data: Data<State>,
+    zone: ZoneRef,
+    path: Path<IndexPath>,
+    _user: Identity,
+) -> HttpResponse {
+    let mut settings = zone.settings.lock().await;
+    if path.index >= settings.mirrors.len() {
+        return HttpResponse::BadRequest().json(GenericResponse::err(
+            "could not remove mirror output",
+            AudioError::NoSuchMirror(path.index).to_string(),
+        ));
+    }
+    settings.mirrors.remove(path.index);
+
+    apply_mirrors(&data, &zone, settings, "successfully removed mirror output").await
+}
+
+/// Saves the mirror outputs of the settings and restarts the player using them.
+/// The settings are released before the player is locked, since other handlers lock the player
+/// before the settings.
+async fn apply_mirrors(
+    data: &State,
+    zone: &Zone,
+    settings: MutexGuard<'_, Settings>,
+    message: &'static str,
+) -> HttpResponse {
+    if settings.write(&zone.settings_path).is_err() {
+        return HttpResponse::InternalServerError().json(GenericResponse::err(
+            "could not change mirror outputs",
+            "could not write to settings file".to_string(),
+        ));
+    }
+    let (mirrors, streams) = (settings.mirrors.clone(), settings.mirror_streams());
+    drop(settings);
+
+    let player = &mut zone.player.lock().await;
+    match player.set_mirrors(mirrors, streams).await {
+        Ok(_) => {
+            zapping::update_preconnections(&data.config, player);
+            HttpResponse::Ok().json(GenericResponse::ok(message))
+        }
         Err(err) => HttpResponse::ServiceUnavailable().json(GenericResponse::err(
-            "could not list devices",
+            "could not restart player",
             err.to_string(),
         )),
     }
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use std::{
+    collections::BTreeMap,
     fs::{self, File},
     io::Write,
     path::Path,
@@ -7,21 +8,148 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
-use crate::audio;
+use crate::{
+    audio::{self, OutputDevice},
+    dsp::{
+        channels::ChannelSettings, compressor::CompressorSettings, equalizer::EqualizerSettings,
+    },
+    output::{self, StreamSettings},
+};
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Settings {
-    pub(crate) alsa_device_index: usize,
+    pub(crate) output_device: OutputDevice,
+    pub(crate) volume_percent: u8,
+    /// Silences the output while retaining the volume
+    #[serde(default)]
+    pub(crate) muted: bool,
+    #[serde(default)]
+    pub(crate) equalizer: EqualizerSettings,
+    #[serde(default)]
+    pub(crate) compressor: CompressorSettings,
+    #[serde(default)]
+    pub(crate) channels: ChannelSettings,
+    #[serde(default)]
+    pub(crate) stream: StreamSettings,
+    /// Additional output devices which play the same audio as the output device
+    #[serde(default)]
+    pub(crate) mirrors: Vec<MirrorOutput>,
+    /// The volume and DSP settings of the output devices which are currently not in use.
+    /// The profiles are identified by the names of the devices.
+    #[serde(default)]
+    pub(crate) device_profiles: BTreeMap<String, DeviceProfile>,
+}
+
+/// An output device which plays the same audio as the main output device, e.g. in another room.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct MirrorOutput {
+    pub(crate) device: OutputDevice,
+    /// The volume relative to the volume of the main output device
+    #[serde(default = "default_mirror_volume")]
+    pub(crate) volume: u8,
+    #[serde(default = "default_mirror_enabled")]
+    pub(crate) enabled: bool,
+}
+
+fn default_mirror_volume() -> u8 {
+    100
+}
+
+fn default_mirror_enabled() -> bool {
+    true
+}
+
+/// The settings which are specific to an output device.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct DeviceProfile {
     pub(crate) volume_percent: u8,
+    #[serde(default)]
+    pub(crate) equalizer: EqualizerSettings,
+    #[serde(default)]
+    pub(crate) compressor: CompressorSettings,
+    #[serde(default)]
+    pub(crate) channels: ChannelSettings,
+    #[serde(default)]
+    pub(crate) stream: StreamSettings,
+}
+
+/// Returns the default output device of the default host.
+/// Machines without a sound card can still run radio, they use the null output.
+pub(crate) fn default_device() -> OutputDevice {
+    audio::default_device(None).unwrap_or_else(|err| {
+        warn!("Could not find the default output device, using the null output: {err}");
+        output::null_device()
+    })
 }
 
 impl Settings {
-    fn default() -> Result<Self> {
-        let alsa_device_idx = audio::default_device_idx()?;
-        Ok(Self {
-            alsa_device_index: alsa_device_idx,
+    fn default(output_device: OutputDevice) -> Self {
+        Self {
+            output_device,
             volume_percent: 100,
-        })
+            muted: false,
+            equalizer: EqualizerSettings::default(),
+            compressor: CompressorSettings::default(),
+            channels: ChannelSettings::default(),
+            stream: StreamSettings::default(),
+            mirrors: vec![],
+            device_profiles: BTreeMap::new(),
+        }
+    }
+
+    /// Returns whether the `device` is the output device or one of the mirrors.
+    /// Devices are compared by their host and name, since their index may have changed.
+    pub(crate) fn uses_device(&self, device: &OutputDevice) -> bool {
+        std::iter::once(&self.output_device)
+            .chain(self.mirrors.iter().map(|m| &m.device))
+            .any(|d| d.host == device.host && d.name == device.name)
+    }
+
+    /// Returns the stream settings of the mirror outputs, which are kept in the profiles of their
+    /// devices. Devices without a profile use the default settings.
+    pub(crate) fn mirror_streams(&self) -> Vec<StreamSettings> {
+        self.mirrors
+            .iter()
+            .map(|mirror| {
+                self.device_profiles
+                    .get(&mirror.device.name)
+                    .map(|profile| profile.stream.clone())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Returns the profile of the device which is currently in use.
+    pub(crate) fn profile(&self) -> DeviceProfile {
+        DeviceProfile {
+            volume_percent: self.volume_percent,
+            equalizer: self.equalizer.clone(),
+            compressor: self.compressor.clone(),
+            channels: self.channels.clone(),
+            stream: self.stream.clone(),
+        }
+    }
+
+    /// Stores the profile of the current device and restores the profile of the `next` device.
+    /// A device without a stored profile starts out with the profile of the current device.
+    /// Returns the profile of the `next` device.
+    /// The stored volume is limited to the `max_volume_percent`, which may have been lowered since.
+    pub(crate) fn switch_device(
+        &mut self,
+        next: OutputDevice,
+        max_volume_percent: u8,
+    ) -> DeviceProfile {
+        self.device_profiles
+            .insert(self.output_device.name.clone(), self.profile());
+        if let Some(profile) = self.device_profiles.remove(&next.name) {
+            self.volume_percent = profile.volume_percent.min(max_volume_percent);
+            self.equalizer = profile.equalizer;
+            self.compressor = profile.compressor;
+            self.channels = profile.channels;
+            self.stream = profile.stream;
+        }
+        self.output_device = next;
+        self.profile()
     }
 
     pub(crate) fn write(&self, path: &Path) -> Result<()> {
@@ -47,20 +175,60 @@ impl Settings {
     }
 }
 
-pub(crate) fn read(path: &Path) -> Result<Settings> {
+/// Reads the settings file at `path`. If it does not exist, it is created with settings which
+/// play on the device returned by `default_device`.
+pub(crate) fn read(path: &Path, default_device: fn() -> OutputDevice) -> Result<Settings> {
     match path.exists() {
         true => {
-            let raw_settings = fs::read_to_string(path)?;
-            let config = toml::from_str::<Settings>(&raw_settings)?;
-            Ok(config)
+            let mut raw_settings = toml::from_str::<toml::Table>(&fs::read_to_string(path)?)?;
+            let migrated = migrate(&mut raw_settings);
+            let settings = toml::Value::Table(raw_settings).try_into::<Settings>()?;
+            if migrated {
+                info!("Migrated settings file to the current format");
+                settings.write(path)?;
+            }
+            Ok(settings)
         }
         false => {
             // create the config file using a default
             fs::create_dir_all(path.parent().unwrap())?;
-            let settings = Settings::default()?;
+            let settings = Settings::default(default_device());
             let mut file = File::create(path)?;
             file.write_all(toml::to_string_pretty(&settings).unwrap().as_bytes())?;
             Ok(settings)
         }
     }
 }
+
+/// Converts a settings file of an older version to the current format.
+/// Returns whether anything was changed.
+fn migrate(raw_settings: &mut toml::Table) -> bool {
+    let mut migrated = false;
+
+    // the output device used to be identified by its position in the device list only
+    if let Some(index) = raw_settings.remove("alsa_device_index") {
+        let index = index.as_integer().unwrap_or_default() as usize;
+        let name = match audio::list_host_devices(None) {
+            Ok(devices) => devices
+                .into_iter()
+                .find(|d| d.index == index)
+                .map(|d| d.name),
+            Err(err) => {
+                warn!("Could not list devices during migration: {err}");
+                None
+            }
+        };
+        let device = OutputDevice {
+            host: None,
+            name: name.unwrap_or_default(),
+            index,
+        };
+        raw_settings.insert(
+            "output_device".to_string(),
+            toml::Value::try_from(device).unwrap(),
+        );
+        migrated = true;
+    }
+
+    migrated
+}
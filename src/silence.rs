@@ -0,0 +1,168 @@
+use rodio::Source;
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::{Config, SilenceAction, SilenceConfig, Station};
+
+/// Determines how the player reacts to streams which only deliver silence.
+#[derive(Clone)]
+pub struct SilencePolicy {
+    pub config: SilenceConfig,
+    pub fallback: Option<Station>,
+}
+
+/// A period of silence which was detected on a stream.
+#[derive(Serialize, Clone)]
+pub struct SilenceIncident {
+    #[serde(rename = "stationId")]
+    pub station_id: String,
+    #[serde(rename = "stationName")]
+    pub station_name: String,
+    /// When the silence was detected (seconds since the UNIX epoch)
+    #[serde(rename = "detectedAt")]
+    pub detected_at: u64,
+    pub action: SilenceAction,
+}
+
+impl SilencePolicy {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let silence = config.silence.clone()?;
+        let fallback = silence
+            .fallback_station
+            .as_ref()
+            .and_then(|id| config.stations.iter().find(|s| &s.id == id))
+            .cloned();
+        Some(Self {
+            config: silence,
+            fallback,
+        })
+    }
+
+    /// Records an incident for the silent `station` and notifies the webhook.
+    pub fn report(&self, station: &Station) -> SilenceIncident {
+        let incident = SilenceIncident {
+            station_id: station.id.clone(),
+            station_name: station.name.clone(),
+            detected_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            action: self.config.action,
+        };
+        warn!(
+            "Stream `{}` has been silent for {} seconds, action: {:?}",
+            station.url, self.config.duration_secs, self.config.action
+        );
+
+        if let Some(url) = self.config.webhook_url.clone() {
+            let body = serde_json::to_string(&incident).unwrap();
+            // the webhook must not delay the recovery of the stream
+            thread::spawn(move || {
+                let response = reqwest::blocking::Client::new()
+                    .post(&url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .send()
+                    .and_then(|r| r.error_for_status());
+                if let Err(err) = response {
+                    warn!("Could not notify silence webhook `{url}`: {err}");
+                }
+            });
+        }
+
+        incident
+    }
+}
+
+/// Watches the decoded samples of the wrapped source for a period of silence.
+/// Once the source has been silent for the configured duration, the `detected` flag is raised.
+/// The flag is raised once per period of silence.
+pub struct SilenceDetector<S>
+where
+    S: Source<Item = i16>,
+{
+    inner: S,
+    detected: Arc<AtomicBool>,
+    threshold: i16,
+    duration: Duration,
+    silent_samples: u64,
+    reported: bool,
+}
+
+impl<S> SilenceDetector<S>
+where
+    S: Source<Item = i16>,
+{
+    pub fn new(inner: S, config: &SilenceConfig, detected: Arc<AtomicBool>) -> Self {
+        let threshold = 10f32.powf(config.threshold_db / 20.0) * i16::MAX as f32;
+        Self {
+            inner,
+            detected,
+            threshold: threshold as i16,
+            duration: Duration::from_secs(config.duration_secs),
+            silent_samples: 0,
+            reported: false,
+        }
+    }
+}
+
+impl<S> Iterator for SilenceDetector<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.inner.next()?;
+
+        if sample.saturating_abs() > self.threshold {
+            self.silent_samples = 0;
+            self.reported = false;
+            return Some(sample);
+        }
+
+        self.silent_samples += 1;
+        let limit = self.duration.as_secs()
+            * self.inner.sample_rate() as u64
+            * self.inner.channels() as u64;
+        if !self.reported && self.silent_samples >= limit {
+            self.reported = true;
+            self.detected.store(true, Ordering::Relaxed);
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for SilenceDetector<S>
+where
+    S: Source<Item = i16>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
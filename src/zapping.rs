@@ -0,0 +1,219 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    audio::Player,
+    config::{Config, Station},
+};
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// A network stream which is connected and buffered in the background
+/// so that playback can start without waiting for a new connection.
+pub struct Preconnection {
+    station: Station,
+    shared: Arc<Shared>,
+}
+
+/// Reads the data of a [`Preconnection`] after it was handed over to the player.
+pub struct PreconnectedReader {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<BufferState>,
+    changed: Condvar,
+}
+
+struct BufferState {
+    data: VecDeque<u8>,
+    capacity: usize,
+    /// Whether the stream is consumed by a player
+    handed_over: bool,
+    /// Whether the stream is no longer needed
+    closed: bool,
+    /// Whether the stream has ended or failed
+    ended: bool,
+}
+
+impl Preconnection {
+    /// Connects to the stream of the station in the background.
+    /// Until the stream is handed over, at most `buffer_bytes` of the most recent data are kept
+    /// and the download is throttled to `bandwidth_limit_kbps` (0 means unlimited).
+    pub fn open(station: Station, buffer_bytes: usize, bandwidth_limit_kbps: u32) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(BufferState {
+                data: VecDeque::with_capacity(buffer_bytes),
+                capacity: buffer_bytes,
+                handed_over: false,
+                closed: false,
+                ended: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let thread_shared = shared.clone();
+        let url = station.url.clone();
+        thread::spawn(move || {
+            if let Err(err) = Self::download(&url, &thread_shared, bandwidth_limit_kbps) {
+                debug!("Preconnected stream `{url}` failed: {err}");
+            }
+            thread_shared.state.lock().unwrap().ended = true;
+            thread_shared.changed.notify_all();
+        });
+
+        debug!("Preconnecting to `{}`...", station.url);
+        Self { station, shared }
+    }
+
+    pub fn station(&self) -> &Station {
+        &self.station
+    }
+
+    /// Whether the stream is still being downloaded.
+    /// A stream which has failed or ended has to be connected again.
+    pub fn is_connected(&self) -> bool {
+        !self.shared.state.lock().unwrap().ended
+    }
+
+    /// Hands the buffered stream over to a player.
+    /// From now on, no data is discarded and the download is no longer throttled.
+    pub fn into_reader(self) -> PreconnectedReader {
+        self.shared.state.lock().unwrap().handed_over = true;
+        PreconnectedReader {
+            shared: self.shared.clone(),
+        }
+    }
+
+    fn download(
+        url: &str,
+        shared: &Shared,
+        bandwidth_limit_kbps: u32,
+    ) -> Result<(), reqwest::Error> {
+        let mut response = reqwest::blocking::get(url)?;
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        let started = Instant::now();
+        let mut throttled_bytes = 0;
+
+        loop {
+            let len = match response.read(&mut chunk) {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(len) => len,
+            };
+
+            let mut state = shared.state.lock().unwrap();
+            if state.handed_over {
+                // apply backpressure instead of discarding data which is yet to be played
+                while state.data.len() >= state.capacity && !state.closed {
+                    state = shared.changed.wait(state).unwrap();
+                }
+            }
+            if state.closed {
+                return Ok(());
+            }
+
+            state.data.extend(&chunk[..len]);
+            if !state.handed_over {
+                // only keep the most recent data so that playback starts close to `live`
+                let excess = state.data.len().saturating_sub(state.capacity);
+                state.data.drain(..excess);
+            }
+            let handed_over = state.handed_over;
+            drop(state);
+            shared.changed.notify_all();
+
+            if !handed_over && bandwidth_limit_kbps > 0 {
+                throttled_bytes += len as u64;
+                let expected =
+                    Duration::from_millis(throttled_bytes * 8 / bandwidth_limit_kbps as u64);
+                if let Some(ahead) = expected.checked_sub(started.elapsed()) {
+                    thread::sleep(ahead);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Preconnection {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if !state.handed_over {
+            trace!("Closing preconnected stream `{}`", self.station.url);
+            state.closed = true;
+            self.shared.changed.notify_all();
+        }
+    }
+}
+
+impl Read for PreconnectedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        while state.data.is_empty() && !state.ended {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+
+        let len = buf.len().min(state.data.len());
+        for (dest, byte) in buf.iter_mut().zip(state.data.drain(..len)) {
+            *dest = byte;
+        }
+        self.shared.changed.notify_all();
+        Ok(len)
+    }
+}
+
+impl Drop for PreconnectedReader {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// Returns the stations to zap through, either the favourites or all configured stations.
+fn zap_order(config: &Config) -> Vec<&Station> {
+    match config.zapping.as_ref().map(|z| &z.favourites) {
+        Some(favourites) if !favourites.is_empty() => favourites
+            .iter()
+            .filter_map(|id| config.stations.iter().find(|s| &s.id == id))
+            .collect(),
+        _ => config.stations.iter().collect(),
+    }
+}
+
+/// Returns the station which is `offset` positions away from the `current` station.
+/// If nothing or a station outside of the zapping order is playing,
+/// zapping starts at the beginning (or the end) of the order.
+pub(crate) fn neighbour(config: &Config, current: Option<&str>, offset: isize) -> Station {
+    let order = zap_order(config);
+    let len = order.len() as isize;
+
+    let target = match order.iter().position(|s| Some(s.id.as_str()) == current) {
+        Some(idx) => (idx as isize + offset).rem_euclid(len),
+        None if offset >= 0 => 0,
+        None => len - 1,
+    };
+    order[target as usize].clone()
+}
+
+/// Preconnects the previous and next stations of the currently playing one if enabled.
+pub(crate) fn update_preconnections(config: &Config, player: &mut Player) {
+    let Some(zapping) = config.zapping.as_ref().filter(|z| z.preconnect) else {
+        return;
+    };
+    let Some(current) = player.curr_station_id() else {
+        return;
+    };
+
+    let mut stations: Vec<Station> = vec![];
+    for offset in [-1, 1] {
+        let station = neighbour(config, Some(&current), offset);
+        if station.id != current && !stations.iter().any(|s| s.id == station.id) {
+            stations.push(station);
+        }
+    }
+    player.preconnect(stations, zapping);
+}
@@ -0,0 +1,145 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    audio::{OutputDevice, Player, PlayerOptions},
+    config::{Config, MAIN_ZONE},
+    dsp::DspChain,
+    output::{self, VirtualHost},
+    settings::{self, Settings},
+    silence::SilencePolicy,
+    zapping, SETTINGS_PATH, ZONES_PATH,
+};
+
+/// A group of output devices which plays independently of the other zones, e.g. a room.
+/// Every zone has its own player and its own settings file.
+pub(crate) struct Zone {
+    pub(crate) name: String,
+    pub(crate) player: Mutex<Player>,
+    pub(crate) settings: Mutex<Settings>,
+    /// The file the settings of the zone are stored in
+    pub(crate) settings_path: PathBuf,
+}
+
+impl Zone {
+    /// Creates the player of the zone using its settings.
+    /// The output device of the settings is replaced by the `device` if it is set.
+    pub(crate) fn new(
+        name: &str,
+        settings: Settings,
+        config: &Config,
+        device: Option<OutputDevice>,
+    ) -> Result<Self> {
+        let player = Player::new(PlayerOptions {
+            volume_percent: settings.volume_percent.min(config.max_volume_percent),
+            muted: settings.muted,
+            device: device.unwrap_or_else(|| settings.output_device.clone()),
+            crossfade: std::time::Duration::from_millis(config.crossfade_ms),
+            fade: std::time::Duration::from_millis(config.fade_ms),
+            max_volume_percent: config.max_volume_percent,
+            dsp: DspChain::new(
+                config.normalization.clone(),
+                settings.equalizer.clone(),
+                settings
+                    .compressor
+                    .active_preset(chrono::Local::now().time()),
+            ),
+            channels: settings.channels.clone(),
+            silence: SilencePolicy::from_config(config),
+            stream: settings.stream.clone(),
+            pipe: config.pipe.clone(),
+            mirrors: settings.mirrors.clone(),
+            mirror_streams: settings.mirror_streams(),
+            listen: config.listen.clone(),
+        })?;
+
+        Ok(Self {
+            name: name.to_string(),
+            player: Mutex::new(player),
+            settings: Mutex::new(settings),
+            settings_path: settings_path(name),
+        })
+    }
+
+    /// Starts the station which is marked as auto start for this zone, if there is one.
+    pub(crate) async fn auto_start(&self, config: &Config) -> Result<()> {
+        let station_id = match self.name == MAIN_ZONE {
+            true => config.stations.iter().find(|s| s.auto_start).map(|s| &s.id),
+            false => config
+                .zones
+                .iter()
+                .find(|z| z.name == self.name)
+                .and_then(|z| z.auto_start.as_ref()),
+        };
+        let Some(station) = station_id.and_then(|id| config.stations.iter().find(|s| &s.id == id))
+        else {
+            debug!("No stream is marked as auto start in zone `{}`", self.name);
+            return Ok(());
+        };
+
+        info!(
+            "Starting auto start stream with ID `{}` in zone `{}`...",
+            station.id, self.name
+        );
+        let mut player = self.player.lock().await;
+        player
+            .play(station.clone())
+            .await
+            .with_context(|| "could not start auto start stream")?;
+        info!(
+            "Successfully started stream `{}` as it is marked as auto start",
+            station.id
+        );
+        zapping::update_preconnections(config, &mut player);
+        Ok(())
+    }
+}
+
+/// Makes sure that no two zones play on the same output device, as output or as mirror.
+pub(crate) fn check_devices(zones: &[(&str, &Settings)]) -> Result<()> {
+    for (index, (name, settings)) in zones.iter().enumerate() {
+        let devices = std::iter::once(&settings.output_device)
+            .chain(settings.mirrors.iter().map(|m| &m.device));
+        for device in devices.filter(|d| !VirtualHost::is_shareable(d)) {
+            if let Some((other, _)) = zones[..index]
+                .iter()
+                .find(|(_, other)| other.uses_device(device))
+            {
+                bail!(
+                    "zone `{name}` cannot play on `{}`: the device is already used by zone `{other}`, \
+                     choose another device in `{}`",
+                    device.name,
+                    settings_path(name).to_string_lossy()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads or creates the settings file of the zone.
+pub(crate) fn read_settings(name: &str) -> Result<Settings> {
+    let path = settings_path(name);
+    // the default device is usually played on by the main zone already, so other zones start out
+    // on the null output until a device is chosen for them
+    let default_device = match name == MAIN_ZONE {
+        true => settings::default_device,
+        false => output::null_device,
+    };
+    settings::read(&path, default_device).with_context(|| {
+        format!(
+            "could not read or create settings file at `{}`",
+            path.to_string_lossy()
+        )
+    })
+}
+
+/// The main zone keeps using the original settings file so that existing setups are unaffected.
+fn settings_path(name: &str) -> PathBuf {
+    match name == MAIN_ZONE {
+        true => PathBuf::from(SETTINGS_PATH),
+        false => Path::new(ZONES_PATH).join(format!("{name}.toml")),
+    }
+}
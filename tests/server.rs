@@ -0,0 +1,373 @@
+use std::{
+    env,
+    ffi::CString,
+    fs::{self, OpenOptions},
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+
+const STARTUP_TIMEOUT_SECS: u64 = 30;
+
+/// A running server which is killed when dropped.
+struct Server {
+    process: Child,
+    port: u16,
+    dir: PathBuf,
+    client: Client,
+    cookie: String,
+}
+
+impl Server {
+    /// Starts the server in a new directory, playing on the `output` given on the command line.
+    fn start(name: &str, output: &str, station_url: &str) -> Self {
+        Self::start_with(name, output, station_url, "")
+    }
+
+    /// Starts the server with additional sections appended to its config.
+    fn start_with(name: &str, output: &str, station_url: &str, config: &str) -> Self {
+        let (dir, port) = create_dir(name, station_url, config);
+        Self::run(dir, port, &["--output", output, "run"])
+    }
+
+    /// Runs the server in the `dir` created by `create_dir` and logs in.
+    fn run(dir: PathBuf, port: u16, args: &[&str]) -> Self {
+        let process = Command::new(env!("CARGO_BIN_EXE_radio"))
+            .args(args)
+            .current_dir(&dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let start = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(
+                start.elapsed() < Duration::from_secs(STARTUP_TIMEOUT_SECS),
+                "the server did not start"
+            );
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        let client = Client::new();
+        let response = client
+            .post(format!("http://127.0.0.1:{port}/api/login"))
+            .header("content-type", "application/json")
+            .body(json!({ "username": "admin", "password": "secret" }).to_string())
+            .send()
+            .unwrap();
+        assert!(response.status().is_success());
+        let cookie = response
+            .headers()
+            .get("set-cookie")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+
+        Self {
+            process,
+            port,
+            dir,
+            client,
+            cookie,
+        }
+    }
+
+    fn post(&self, path: &str, body: Value) -> Value {
+        let response = self
+            .client
+            .post(format!("http://127.0.0.1:{}{path}", self.port))
+            .header("cookie", &self.cookie)
+            .header("content-type", "application/json")
+            .body(body.to_string())
+            .send()
+            .unwrap();
+        assert!(response.status().is_success(), "POST {path} failed");
+        serde_json::from_str(&response.text().unwrap()).unwrap()
+    }
+
+    fn status(&self) -> Value {
+        let response = self
+            .client
+            .get(format!("http://127.0.0.1:{}/api/status", self.port))
+            .header("cookie", &self.cookie)
+            .send()
+            .unwrap();
+        serde_json::from_str(&response.text().unwrap()).unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Creates the working directory of a server with a config listening on a free port.
+fn create_dir(name: &str, station_url: &str, config: &str) -> (PathBuf, u16) {
+    let dir = env::temp_dir().join(format!("radio-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("images")).unwrap();
+    fs::write(dir.join("images/test.png"), []).unwrap();
+
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    fs::write(
+        dir.join("config.toml"),
+        format!(
+            r#"
+port = {port}
+session_key = "a_session_key_for_the_integration_test_which_is_over_64_characters_long"
+
+[[users]]
+username = "admin"
+password = "secret"
+
+[[stations]]
+id = "test"
+name = "Test Radio"
+description = "A local test stream"
+url = "{station_url}"
+image_file = "test.png"
+auto_restart = false
+auto_start = false
+
+{config}"#
+        ),
+    )
+    .unwrap();
+
+    (dir, port)
+}
+
+/// Returns `frames` silent MPEG-1 Layer III frames (128 kbit/s, 44.1 kHz, joint stereo).
+fn mp3(frames: usize) -> Vec<u8> {
+    let mut frame = vec![0; 417];
+    frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0xc4]);
+    frame.repeat(frames)
+}
+
+/// Serves the body to every request, returns the URL of the stream.
+fn serve(body: Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/stream.mp3", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let body = body.clone();
+            thread::spawn(move || {
+                let mut request = vec![];
+                let mut byte = [0];
+                while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) > 0 {
+                    request.push(byte[0]);
+                }
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: {}\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.write_all(header.as_bytes());
+                let _ = stream.write_all(&body);
+            });
+        }
+    });
+    url
+}
+
+fn wait_for_station(server: &Server, station_id: Option<&str>) {
+    let start = Instant::now();
+    while server.status()["stationId"].as_str() != station_id {
+        assert!(
+            start.elapsed() < Duration::from_secs(STARTUP_TIMEOUT_SECS),
+            "the station is not {station_id:?}"
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn plays_on_null_output() {
+    // about 10 seconds of audio
+    let server = Server::start("null", "null", &serve(mp3(400)));
+    assert_eq!(server.status()["stationId"], Value::Null);
+
+    server.post("/api/play", json!({ "stationId": "test" }));
+    assert_eq!(server.status()["stationId"], "test");
+
+    // switching to the same station again crossfades on the same output
+    server.post("/api/play", json!({ "stationId": "test" }));
+    thread::sleep(Duration::from_secs(2));
+    let status = server.status();
+    assert_eq!(status["stationId"], "test");
+    assert!(status["positionSecs"].as_u64().unwrap() >= 1);
+
+    server.post("/api/stop", json!({}));
+    wait_for_station(&server, None);
+}
+
+#[test]
+fn records_crossfaded_stations_into_one_wav_file() {
+    let path = env::temp_dir().join(format!("radio-recording-{}.wav", std::process::id()));
+    let server = Server::start(
+        "wav",
+        &format!("wav:{}", path.to_string_lossy()),
+        &serve(mp3(400)),
+    );
+
+    let start = Instant::now();
+    server.post("/api/play", json!({ "stationId": "test" }));
+    thread::sleep(Duration::from_secs(1));
+    server.post("/api/play", json!({ "stationId": "test" }));
+    thread::sleep(Duration::from_secs(2));
+    server.post("/api/stop", json!({}));
+    wait_for_station(&server, None);
+    // nothing is recorded while stopped, apart from the fade out
+    let stopped = Duration::from_secs(4);
+    thread::sleep(stopped);
+    // playing again continues the recording instead of starting a new file
+    server.post("/api/play", json!({ "stationId": "test" }));
+    thread::sleep(Duration::from_secs(1));
+    drop(server);
+
+    let duration = assert_recording(&path, Duration::from_secs(3));
+    assert!(
+        duration < start.elapsed() - stopped / 2,
+        "the recording is {duration:?} long"
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+/// Checks that the WAV file is valid and at least `min_duration` long, returns its duration.
+fn assert_recording(path: &Path, min_duration: Duration) -> Duration {
+    let mut reader = hound::WavReader::open(path).unwrap();
+    let spec = reader.spec();
+    let samples = reader
+        .samples::<i16>()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let frames = samples.len() as u64 / spec.channels as u64;
+    assert_eq!(reader.duration() as u64, frames);
+    let duration = Duration::from_millis(frames * 1000 / spec.sample_rate as u64);
+    assert!(
+        duration >= min_duration,
+        "the recording is only {frames} frames long"
+    );
+    duration
+}
+
+#[test]
+fn stops_playback_once_the_pipe_reader_is_gone() {
+    let dir = env::temp_dir().join(format!("radio-fifo-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let fifo = dir.join("fifo");
+    let path = CString::new(fifo.to_string_lossy().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+    // opening the pipe for reading and writing does not wait for the server to open it
+    let mut reader = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&fifo)
+        .unwrap();
+
+    let server = Server::start_with(
+        "pipe",
+        "pipe",
+        // the stream is far longer than the test, so it only stops because the pipe has failed
+        &serve(mp3(4000)),
+        &format!(
+            "[pipe]\npath = \"{}\"\nsample_rate = 48000\nchannels = 2\nformat = \"s16le\"\n",
+            fifo.to_string_lossy()
+        ),
+    );
+    server.post("/api/play", json!({ "stationId": "test" }));
+    server.post("/api/play", json!({ "stationId": "test" }));
+
+    // the crossfading stations are written into the pipe by a single writer in real time
+    let duration = Duration::from_secs(3);
+    let start = Instant::now();
+    let mut received = 0;
+    let mut buffer = [0; 4096];
+    while start.elapsed() < duration {
+        received += reader.read(&mut buffer).unwrap();
+    }
+    let real_time = 48_000 * 2 * 2 * duration.as_secs() as usize;
+    assert!(
+        received < real_time * 3 / 2,
+        "received {received} bytes, expected about {real_time} bytes"
+    );
+
+    drop(reader);
+    wait_for_station(&server, None);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Runs the server until it exits and returns whether it has failed to start.
+fn fails_to_start(dir: &Path, args: &[&str]) -> bool {
+    let status = Command::new(env!("CARGO_BIN_EXE_radio"))
+        .args(args)
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    !status.success()
+}
+
+#[test]
+fn rejects_output_option_with_zones() {
+    let (dir, _) = create_dir(
+        "zones",
+        "http://127.0.0.1:1/",
+        "[[zones]]\nname = \"kitchen\"\n",
+    );
+
+    assert!(fails_to_start(&dir, &["--output", "null", "run"]));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rejects_zones_sharing_a_device() {
+    let (dir, _) = create_dir(
+        "shared",
+        "http://127.0.0.1:1/",
+        "[[zones]]\nname = \"kitchen\"\n",
+    );
+    let recording = dir.join("recording.wav");
+    let settings = format!(
+        "volume_percent = 50\n\n[output_device]\nhost = \"WAV\"\nname = \"{}\"\nindex = 0\n",
+        recording.to_string_lossy()
+    );
+    fs::create_dir_all(dir.join("zones")).unwrap();
+    fs::write(dir.join("settings.toml"), &settings).unwrap();
+    fs::write(dir.join("zones/kitchen.toml"), &settings).unwrap();
+
+    assert!(fails_to_start(&dir, &["run"]));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn starts_new_zone_on_null_output() {
+    let (dir, port) = create_dir(
+        "new-zone",
+        "http://127.0.0.1:1/",
+        "[[zones]]\nname = \"kitchen\"\n",
+    );
+    let server = Server::run(dir, port, &["run"]);
+
+    let settings = fs::read_to_string(server.dir.join("zones/kitchen.toml")).unwrap();
+    assert!(settings.contains("host = \"Null\""), "{settings}");
+}